@@ -0,0 +1,96 @@
+//! 识别答题页面的耗时，分别统计每个阶段：`cargo bench --bench detect`
+//!
+//! 程序只有二进制目标，这里按 `src/main.rs` 的方式引入所有模块。
+//! 运行测试时也会编译这个目标，模块中的测试不会被编译，测试用的导入没有用到
+
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/adb.rs"]
+mod adb;
+#[path = "../src/answerer/mod.rs"]
+mod answerer;
+#[path = "../src/bank.rs"]
+mod bank;
+#[path = "../src/community.rs"]
+mod community;
+#[path = "../src/compose.rs"]
+mod compose;
+#[path = "../src/context.rs"]
+mod context;
+#[path = "../src/fallback.rs"]
+mod fallback;
+#[path = "../src/few_shot.rs"]
+mod few_shot;
+#[path = "../src/fixtures.rs"]
+mod fixtures;
+#[path = "../src/logging.rs"]
+mod logging;
+#[path = "../src/mock_llm.rs"]
+mod mock_llm;
+#[path = "../src/page.rs"]
+mod page;
+#[path = "../src/prompt.rs"]
+mod prompt;
+#[path = "../src/stability.rs"]
+mod stability;
+#[path = "../src/vision.rs"]
+mod vision;
+
+use std::time::Duration;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+use vision::{DEFAULT_BAND, Pipeline};
+
+fn bench_detect(c: &mut Criterion) {
+    let screen = fixtures::question_screen(3);
+    let mut group = c.benchmark_group("detect");
+    for (scale, band) in [
+        (1, (0.0, 1.0)),
+        (1, DEFAULT_BAND),
+        (2, DEFAULT_BAND),
+        (3, DEFAULT_BAND),
+    ] {
+        let pipeline = Pipeline::new(scale, band);
+        let stages: Vec<_> = pipeline
+            .detect(&screen.image)
+            .timings
+            .stages
+            .iter()
+            .map(|x| x.0)
+            .collect();
+        let param = format!("scale-{scale}-band-{}-{}", band.0, band.1);
+        group.bench_with_input(
+            BenchmarkId::new("total", &param),
+            &pipeline,
+            |b, pipeline| {
+                b.iter(|| {
+                    let detection = pipeline.detect(&screen.image);
+                    assert!(detection.page.is_ok(), "{param}");
+                })
+            },
+        );
+        // 每次运行整个流程，只累计这个阶段的耗时
+        for stage in stages {
+            group.bench_with_input(BenchmarkId::new(stage, &param), &pipeline, |b, pipeline| {
+                b.iter_custom(|iters| {
+                    (0..iters)
+                        .map(|_| {
+                            let detection = pipeline.detect(&screen.image);
+                            detection
+                                .timings
+                                .stages
+                                .iter()
+                                .find(|x| x.0 == stage)
+                                .map_or(Duration::ZERO, |x| x.1)
+                        })
+                        .sum()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_detect);
+criterion_main!(benches);
@@ -0,0 +1,299 @@
+use std::sync::Arc;
+
+use reqwest::blocking::{Client, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{compose::Question, context::Context};
+
+use super::{
+    Answerer, Endpoint, Reply, ResponseError, Turn, Usage, Vcr, new_headers, parse_body,
+    parse_reply, reply_from_json, reply_schema, turns,
+};
+
+/// 结构化输出时模型调用的工具
+const REPLY_TOOL: &str = "submit_answer";
+
+/// Anthropic 的 Messages API
+pub struct Anthropic {
+    endpoint: Endpoint,
+    version: String,
+    client: Client,
+    max_tokens: u32,
+    /// 开启思考时思考使用的 token 上限
+    thinking_budget: Option<u32>,
+    /// 通过工具调用提交答案
+    structured: bool,
+
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl Anthropic {
+    pub fn from_args(ctx: &Context, vcr: Option<Arc<Vcr>>) -> Self {
+        let thinking_budget = ctx.answer_thinking.then_some(ctx.answer_thinking_budget);
+        Self::new(
+            Endpoint::from_args(ctx, vcr),
+            ctx.anthropic_version.clone(),
+            ctx.api_max_tokens,
+            thinking_budget,
+            ctx.answer_structured,
+        )
+    }
+
+    pub fn new(
+        endpoint: Endpoint,
+        version: String,
+        max_tokens: u32,
+        thinking_budget: Option<u32>,
+        structured: bool,
+    ) -> Self {
+        Self {
+            endpoint,
+            version,
+            client: Client::new(),
+            max_tokens,
+            thinking_budget,
+            structured,
+            input_tokens: 0,
+            output_tokens: 0,
+        }
+    }
+
+    fn post(&self, question: &Question) -> Option<Response> {
+        let headers = new_headers(&[
+            ("Content-Type", "application/json"),
+            ("x-api-key", &self.endpoint.key),
+            ("anthropic-version", &self.version),
+        ]);
+        let messages: Vec<_> = turns(question, self.structured)
+            .into_iter()
+            .map(|turn| match turn {
+                Turn::User { images, text } => {
+                    let mut content: Vec<_> = images
+                        .into_iter()
+                        .map(|img| {
+                            let image = self.endpoint.image.encode(img);
+                            json!({
+                                "type": "image",
+                                "source": {
+                                    "type": "base64",
+                                    "media_type": image.mime,
+                                    "data": image.data,
+                                },
+                            })
+                        })
+                        .collect();
+                    content.push(json!({
+                        "type": "text",
+                        "text": text,
+                    }));
+                    json!({
+                        "role": "user",
+                        "content": content,
+                    })
+                }
+                Turn::Assistant(text) => json!({
+                    "role": "assistant",
+                    "content": text,
+                }),
+            })
+            .collect();
+        let mut body = json!({
+            "model": self.endpoint.model,
+            "max_tokens": self.max_tokens,
+            "messages": messages,
+        });
+        if let Some(system) = &question.prompt.system {
+            body["system"] = json!(system);
+        }
+        if let Some(budget) = self.thinking_budget {
+            // 思考的 token 也计入 max_tokens，需要留出回答的空间
+            body["max_tokens"] = json!(self.max_tokens + budget);
+            body["thinking"] = json!({
+                "type": "enabled",
+                "budget_tokens": budget,
+            });
+        }
+        if self.structured {
+            body["tools"] = json!([
+                {
+                    "name": REPLY_TOOL,
+                    "description": "提交选择题的答案以及对答案的把握",
+                    "input_schema": reply_schema(question),
+                },
+            ]);
+            // 开启思考时不能强制调用工具，只能让模型自己决定
+            body["tool_choice"] = if self.thinking_budget.is_some() {
+                json!({ "type": "auto" })
+            } else {
+                json!({ "type": "tool", "name": REPLY_TOOL })
+            };
+        }
+        let body = body.to_string();
+        log::debug!("request {}", self.endpoint.url);
+        let resp = self.endpoint.send(|| {
+            self.client
+                .post(&self.endpoint.url)
+                .headers(headers.clone())
+                .body(body.clone())
+                .timeout(self.endpoint.timeout)
+        })?;
+        Some(resp)
+    }
+}
+
+/// Messages API 的响应
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    usage: MessagesUsage,
+    stop_reason: Option<String>,
+}
+
+/// 回答中的一块，`text`、`thinking` 或者 `tool_use`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    text: Option<String>,
+    name: Option<String>,
+    input: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MessagesUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl Answerer for Anthropic {
+    fn answer(&mut self, question: &Question) -> Option<Reply> {
+        let resp: MessagesResponse = parse_body(self.post(question)?)
+            .inspect_err(|e| log::warn!("{e}"))
+            .ok()?;
+        // 开启思考时 content 中还有 thinking 块，只取 text 块
+        let tool_input = resp
+            .content
+            .iter()
+            .find(|x| x.kind == "tool_use" && x.name.as_deref() == Some(REPLY_TOOL))
+            .and_then(|x| x.input.as_ref());
+        let message: String = resp
+            .content
+            .iter()
+            .filter(|x| x.kind == "text")
+            .filter_map(|x| x.text.as_deref())
+            .collect();
+        let (input_tokens, output_tokens) = (resp.usage.input_tokens, resp.usage.output_tokens);
+        log::debug!(
+            "tokens: input: {input_tokens}, output: {output_tokens}, total: {}",
+            input_tokens + output_tokens
+        );
+        self.input_tokens += input_tokens;
+        self.output_tokens += output_tokens;
+
+        if resp.stop_reason.as_deref() == Some("refusal") {
+            log::warn!("{}", ResponseError::Refusal(message));
+            return None;
+        }
+        if let Some(input) = tool_input {
+            log::debug!("tool input: {input}");
+            if let Some(reply) = reply_from_json(input) {
+                return Some(reply);
+            }
+        }
+        log::debug!("raw answer: {}", message);
+        parse_reply(&message)
+    }
+
+    fn usage(&self) -> Usage {
+        self.endpoint
+            .cost
+            .usage(self.input_tokens, self.output_tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        answerer::{Answer, mock::MockServer},
+        logging,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_answer() {
+        logging::init_for_test();
+        let resp = json!({
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                {"type": "thinking", "thinking": "选项 B 是正确的", "signature": "sig"},
+                {"type": "text", "text": "B"},
+            ],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 321, "output_tokens": 12},
+        });
+        let server = MockServer::start(vec![(200, resp.to_string())]);
+        let endpoint = Endpoint::for_test(server.url("/v1/messages"));
+        let mut answerer = Anthropic::new(endpoint, "2023-06-01".to_owned(), 64, Some(1024), false);
+
+        let reply = answerer.answer(&Question::for_test()).unwrap();
+        assert!(matches!(reply.answer, Answer::B));
+        let usage = answerer.usage();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (321, 12));
+
+        let requests = server.requests();
+        let request = &requests[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/v1/messages");
+        assert_eq!(request.header("x-api-key"), Some("sk-test"));
+        assert_eq!(request.header("anthropic-version"), Some("2023-06-01"));
+        let body = request.json();
+        assert_eq!(body["model"], "test-model");
+        assert_eq!(body["max_tokens"], 64 + 1024);
+        assert_eq!(body["thinking"]["budget_tokens"], 1024);
+        let image = &body["messages"][0]["content"][0];
+        assert_eq!(image["type"], "image");
+        assert_eq!(image["source"]["media_type"], "image/jpeg");
+        assert!(!image["source"]["data"].as_str().unwrap().is_empty());
+        assert!(body.get("tools").is_none());
+    }
+
+    #[test]
+    fn test_answer_tool() {
+        logging::init_for_test();
+        let resp = json!({
+            "id": "msg_02",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                {
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": REPLY_TOOL,
+                    "input": {"answer": "C", "confidence": 0.65},
+                },
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 480, "output_tokens": 40},
+        });
+        let server = MockServer::start(vec![(200, resp.to_string())]);
+        let endpoint = Endpoint::for_test(server.url("/v1/messages"));
+        let mut answerer = Anthropic::new(endpoint, "2023-06-01".to_owned(), 64, None, true);
+
+        let reply = answerer.answer(&Question::for_test()).unwrap();
+        assert!(matches!(reply.answer, Answer::C));
+        assert_eq!(reply.confidence, Some(0.65));
+
+        let body = server.requests()[0].json();
+        assert_eq!(body["tools"][0]["name"], REPLY_TOOL);
+        assert_eq!(body["tool_choice"]["type"], "tool");
+        assert_eq!(body["tool_choice"]["name"], REPLY_TOOL);
+    }
+}
@@ -0,0 +1,77 @@
+use super::Reply;
+
+/// 统计答案的概率分布
+///
+/// 答题过程中无法知道答案是否正确，概率之和是期望答对的题数，
+/// 可以和最终的得分对比来判断模型给出的概率是否可信
+#[derive(Debug, Default)]
+pub struct Calibration {
+    /// 按概率分成 10 段的题数
+    buckets: [u32; 10],
+    /// 有概率的题数以及概率之和
+    count: u32,
+    sum: f64,
+    /// 没有概率的题数
+    unknown: u32,
+}
+
+impl Calibration {
+    pub fn record(&mut self, reply: &Reply) {
+        let Some(probability) = reply.probability() else {
+            self.unknown += 1;
+            return;
+        };
+        let index = ((probability * 10.0) as usize).min(9);
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.sum += probability as f64;
+    }
+
+    pub fn log_summary(&self) {
+        if self.count == 0 {
+            return;
+        }
+        log::info!(
+            "calibration: questions with probability: {}, without: {}, mean: {:.3}, expected correct: {:.1}",
+            self.count,
+            self.unknown,
+            self.sum / self.count as f64,
+            self.sum
+        );
+        for (i, count) in self.buckets.iter().enumerate() {
+            if *count > 0 {
+                log::info!(
+                    "calibration: [{:.1}, {:.1}{}: {}",
+                    i as f32 / 10.0,
+                    (i + 1) as f32 / 10.0,
+                    if i == 9 { "]" } else { ")" },
+                    count
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::answerer::Answer;
+
+    use super::*;
+
+    #[test]
+    fn test_calibration() {
+        let mut calibration = Calibration::default();
+        let reply = |answer, confidence| Reply {
+            confidence,
+            ..Reply::from(answer)
+        };
+        calibration.record(&reply(Answer::A, Some(0.95)));
+        calibration.record(&reply(Answer::B, Some(1.0)));
+        calibration.record(&reply(Answer::C, Some(0.31)));
+        calibration.record(&reply(Answer::D, None));
+        assert_eq!(calibration.buckets[9], 2);
+        assert_eq!(calibration.buckets[3], 1);
+        assert_eq!((calibration.count, calibration.unknown), (3, 1));
+        assert!((calibration.sum - 2.26).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use crate::{compose::Question, context::Context};
+
+use super::{Answerer, Reply, Usage, Vcr};
+
+/// 重新回答的原因
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Escalation {
+    /// 便宜的模型没有给出有效的答案
+    Failed,
+    /// 答案的概率低于阈值
+    LowConfidence,
+    /// 便宜的模型两次回答不一致
+    Disagree,
+}
+
+/// 先用便宜的模型回答，必要时再交给更强的模型
+pub struct Cascade {
+    cheap: Box<dyn Answerer>,
+    strong: Box<dyn Answerer>,
+    /// 答案的概率低于该值时重新回答
+    threshold: Option<f32>,
+    /// 便宜的模型回答两次，答案不一致时重新回答
+    disagree: bool,
+
+    questions: u32,
+    failed: u32,
+    low_confidence: u32,
+    disagreed: u32,
+    /// 重新回答后答案改变的题数
+    changed: u32,
+    /// 更强的模型也没有给出有效答案的题数
+    strong_failed: u32,
+}
+
+impl Cascade {
+    /// 设置了任一重新回答的条件时才使用
+    pub fn enabled(ctx: &Context) -> bool {
+        ctx.answer_escalate_below.is_some() || ctx.answer_escalate_on_disagree
+    }
+
+    pub fn from_args(ctx: &Context, vcr: Option<Arc<Vcr>>) -> Self {
+        let mut strong_ctx = ctx.clone();
+        strong_ctx.answer_thinking = true;
+        if let Some(model) = &ctx.answer_escalate_model {
+            strong_ctx.api_model = model.clone();
+        }
+        log::debug!(
+            "cascade: cheap model: {}, strong model: {}",
+            ctx.api_model,
+            strong_ctx.api_model
+        );
+        Self::new(
+            super::base_from_args(ctx, vcr.clone()),
+            super::base_from_args(&strong_ctx, vcr),
+            ctx.answer_escalate_below,
+            ctx.answer_escalate_on_disagree,
+        )
+    }
+
+    fn new(
+        cheap: Box<dyn Answerer>,
+        strong: Box<dyn Answerer>,
+        threshold: Option<f32>,
+        disagree: bool,
+    ) -> Self {
+        Self {
+            cheap,
+            strong,
+            threshold,
+            disagree,
+            questions: 0,
+            failed: 0,
+            low_confidence: 0,
+            disagreed: 0,
+            changed: 0,
+            strong_failed: 0,
+        }
+    }
+
+    fn escalated(&self) -> u32 {
+        self.failed + self.low_confidence + self.disagreed
+    }
+
+    /// 判断便宜的模型的回答是否需要重新回答
+    fn check(&mut self, question: &Question, reply: Option<&Reply>) -> Option<Escalation> {
+        let Some(reply) = reply else {
+            return Some(Escalation::Failed);
+        };
+        if let (Some(threshold), Some(probability)) = (self.threshold, reply.probability())
+            && probability < threshold
+        {
+            return Some(Escalation::LowConfidence);
+        }
+        if self.disagree {
+            let second = self.cheap.answer(question);
+            log::debug!(
+                "cascade: second sample: {:?}",
+                second.as_ref().map(|x| x.answer)
+            );
+            if second.is_none_or(|x| x.answer as usize != reply.answer as usize) {
+                return Some(Escalation::Disagree);
+            }
+        }
+        None
+    }
+}
+
+impl Answerer for Cascade {
+    fn answer(&mut self, question: &Question) -> Option<Reply> {
+        self.questions += 1;
+        let reply = self.cheap.answer(question);
+        let Some(escalation) = self.check(question, reply.as_ref()) else {
+            return reply;
+        };
+        match escalation {
+            Escalation::Failed => self.failed += 1,
+            Escalation::LowConfidence => self.low_confidence += 1,
+            Escalation::Disagree => self.disagreed += 1,
+        }
+        log::info!(
+            "Escalating to the strong model: {:?}, cheap answer: {:?}",
+            escalation,
+            reply.as_ref().map(|x| x.answer)
+        );
+        let Some(strong) = self.strong.answer(question) else {
+            self.strong_failed += 1;
+            return reply;
+        };
+        if reply.is_some_and(|x| x.answer as usize != strong.answer as usize) {
+            self.changed += 1;
+        }
+        Some(strong)
+    }
+
+    fn usage(&self) -> Usage {
+        self.cheap.usage() + self.strong.usage()
+    }
+
+    fn usage_details(&self) -> Vec<(String, Usage)> {
+        vec![
+            ("cheap".to_owned(), self.cheap.usage()),
+            ("strong".to_owned(), self.strong.usage()),
+        ]
+    }
+
+    fn log_summary(&self) {
+        self.cheap.log_summary();
+        self.strong.log_summary();
+        let escalated = self.escalated();
+        log::info!(
+            "cascade: questions: {}, escalated: {} (failed: {}, low confidence: {}, disagree: {}), changed: {}, strong failed: {}",
+            self.questions,
+            escalated,
+            self.failed,
+            self.low_confidence,
+            self.disagreed,
+            self.changed,
+            self.strong_failed
+        );
+        if let Some(saved) = savings(self.questions, escalated, self.strong.usage(), self.usage()) {
+            log::info!(
+                "cascade: estimated cost if every question used the strong model: {:.3}RMB, saved at most: {:.3}RMB",
+                saved + self.usage().cost,
+                saved
+            );
+        }
+    }
+}
+
+/// 按更强的模型每次回答的平均费用估算全部使用它的费用，返回节省的费用的上限
+///
+/// 交给更强的模型的都是难题，思考的 token 往往比平均多，按它们的平均费用估算会偏高
+fn savings(questions: u32, escalated: u32, strong: Usage, total: Usage) -> Option<f64> {
+    if escalated == 0 {
+        return None;
+    }
+    let strong_only = strong.cost / escalated as f64 * questions as f64;
+    Some(strong_only - total.cost)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        answerer::{Answer, Cost},
+        compose::QuestionText,
+    };
+
+    use super::*;
+
+    /// 按顺序返回预设的回答，每次回答使用 100 个输入 token
+    struct Scripted {
+        replies: Vec<Option<Reply>>,
+        calls: u64,
+        cost: Cost,
+    }
+
+    impl Scripted {
+        fn new(replies: Vec<Option<Reply>>, cost: f64) -> Box<Self> {
+            let cost = Cost {
+                input: cost,
+                output: cost,
+            };
+            Box::new(Self {
+                replies,
+                calls: 0,
+                cost,
+            })
+        }
+    }
+
+    impl Answerer for Scripted {
+        fn answer(&mut self, _question: &Question) -> Option<Reply> {
+            self.calls += 1;
+            self.replies.remove(0)
+        }
+
+        fn usage(&self) -> Usage {
+            self.cost.usage(self.calls * 100, 0)
+        }
+    }
+
+    fn reply(answer: Answer, confidence: f32) -> Option<Reply> {
+        Some(Reply {
+            confidence: Some(confidence),
+            ..Reply::from(answer)
+        })
+    }
+
+    fn question() -> Question {
+        Question {
+            text: Some(QuestionText {
+                stem: "1 + 1 = ?".to_owned(),
+                options: vec![
+                    "1".to_owned(),
+                    "2".to_owned(),
+                    "3".to_owned(),
+                    "4".to_owned(),
+                ],
+            }),
+            ..Question::for_test()
+        }
+    }
+
+    #[test]
+    fn test_cascade() {
+        let cheap = Scripted::new(
+            vec![
+                reply(Answer::A, 0.9),
+                reply(Answer::B, 0.3),
+                None,
+                reply(Answer::D, 0.95),
+                reply(Answer::C, 0.95),
+            ],
+            1.0,
+        );
+        let strong = Scripted::new(vec![reply(Answer::C, 0.8), reply(Answer::A, 0.9)], 10.0);
+        let mut cascade = Cascade::new(cheap, strong, Some(0.5), false);
+        let answers: Vec<_> = (0..4)
+            .map(|_| cascade.answer(&question()).unwrap().answer as usize)
+            .collect();
+        assert_eq!(answers, [0, 2, 0, 3]);
+        assert_eq!(
+            (cascade.failed, cascade.low_confidence, cascade.changed),
+            (1, 1, 1)
+        );
+
+        // 全部使用更强的模型需要 4 * 100 * 10 / 1M，实际使用了 4 * 100 * 1 / 1M + 2 * 100 * 10 / 1M
+        let saved = savings(4, 2, cascade.strong.usage(), cascade.usage()).unwrap();
+        assert!((saved - 0.0016).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cascade_disagree() {
+        let cheap = Scripted::new(
+            vec![
+                reply(Answer::A, 0.9),
+                reply(Answer::A, 0.9),
+                reply(Answer::B, 0.9),
+                reply(Answer::C, 0.9),
+            ],
+            1.0,
+        );
+        let strong = Scripted::new(vec![reply(Answer::B, 0.8)], 10.0);
+        let mut cascade = Cascade::new(cheap, strong, None, true);
+        assert!(matches!(
+            cascade.answer(&question()).unwrap().answer,
+            Answer::A
+        ));
+        assert!(matches!(
+            cascade.answer(&question()).unwrap().answer,
+            Answer::B
+        ));
+        assert_eq!((cascade.disagreed, cascade.changed), (1, 0));
+    }
+}
@@ -0,0 +1,181 @@
+//! OpenAI 兼容接口的响应，各家的实现不完全相同，缺少的字段使用默认值
+
+use serde::Deserialize;
+
+use super::ResponseError;
+
+/// `/chat/completions` 的响应，也是流式回答中的每个数据块
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(super) struct ChatCompletion {
+    pub choices: Vec<Choice>,
+    pub usage: Option<ChatUsage>,
+    /// 旧版本的 llama.cpp 只在 timings 中返回 token 数
+    pub timings: Option<Timings>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(super) struct Choice {
+    pub message: Message,
+    /// 流式回答中这次新增的部分
+    pub delta: Message,
+    pub logprobs: Option<serde_json::Value>,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(super) struct Message {
+    pub content: Option<Content>,
+    /// 模型拒绝回答时的说明
+    pub refusal: Option<String>,
+}
+
+/// 可以是字符串，也可以是由多个部分组成的数组
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(super) enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(super) struct ContentPart {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(super) struct ChatUsage {
+    pub prompt_tokens: u64,
+    /// 包括思考的 token
+    pub completion_tokens: u64,
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(super) struct CompletionTokensDetails {
+    pub reasoning_tokens: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(super) struct Timings {
+    pub prompt_n: u64,
+    pub predicted_n: u64,
+}
+
+/// 一次或多次请求使用的 token
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) struct Tokens {
+    pub prompt: u64,
+    /// 包括思考的 token
+    pub completion: u64,
+    pub reasoning: u64,
+}
+
+impl std::ops::Add for Tokens {
+    type Output = Tokens;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Tokens {
+            prompt: self.prompt + rhs.prompt,
+            completion: self.completion + rhs.completion,
+            reasoning: self.reasoning + rhs.reasoning,
+        }
+    }
+}
+
+impl Content {
+    /// 拼接所有的文字部分
+    pub fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .filter(|x| x.kind == "text" || x.kind.is_empty())
+                .filter_map(|x| x.text.as_deref())
+                .collect(),
+        }
+    }
+}
+
+impl ChatCompletion {
+    /// 第一个回答的文字和 logprobs，没有回答或者模型拒绝回答时返回错误
+    pub fn reply(&self) -> Result<(String, Option<&serde_json::Value>), ResponseError> {
+        let choice = self
+            .choices
+            .first()
+            .ok_or_else(|| ResponseError::Empty("no choices".to_owned()))?;
+        if let Some(refusal) = &choice.message.refusal {
+            return Err(ResponseError::Refusal(refusal.clone()));
+        }
+        let content = choice.message.content.as_ref().ok_or_else(|| {
+            let reason = choice.finish_reason.as_deref().unwrap_or("unknown");
+            ResponseError::Empty(format!("no content, finish reason: {reason}"))
+        })?;
+        Ok((content.text(), choice.logprobs.as_ref()))
+    }
+
+    /// 流式回答的数据块中这次新增的文字
+    pub fn delta(&self) -> Option<String> {
+        Some(self.choices.first()?.delta.content.as_ref()?.text())
+    }
+
+    pub fn tokens(&self) -> Option<Tokens> {
+        if let Some(usage) = &self.usage {
+            return Some(Tokens {
+                prompt: usage.prompt_tokens,
+                completion: usage.completion_tokens,
+                reasoning: usage
+                    .completion_tokens_details
+                    .as_ref()
+                    .map_or(0, |x| x.reasoning_tokens),
+            });
+        }
+        self.timings.as_ref().map(|x| Tokens {
+            prompt: x.prompt_n,
+            completion: x.predicted_n,
+            reasoning: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> ChatCompletion {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_reply() {
+        let resp = parse(
+            r#"{"choices": [{"message": {"content": [{"type": "text", "text": "答案："}, {"type": "image_url"}, {"type": "text", "text": "B"}]}}]}"#,
+        );
+        assert_eq!(resp.reply().unwrap().0, "答案：B");
+        // 没有 usage 时不计算 token
+        assert!(resp.tokens().is_none());
+
+        let resp = parse(
+            r#"{"choices": [{"message": {"content": null, "refusal": "I can't help with that."}}], "usage": {"prompt_tokens": 10, "completion_tokens": 0, "completion_tokens_details": null}}"#,
+        );
+        assert!(matches!(resp.reply(), Err(ResponseError::Refusal(_))));
+        assert_eq!(resp.tokens().unwrap().prompt, 10);
+
+        let resp =
+            parse(r#"{"choices": [{"message": {"content": null}, "finish_reason": "length"}]}"#);
+        let error = resp.reply().unwrap_err().to_string();
+        assert!(error.contains("length"), "{error}");
+        assert!(parse("{}").reply().is_err());
+
+        let resp = parse(r#"{"timings": {"prompt_n": 530, "predicted_n": 3}}"#);
+        assert_eq!(resp.tokens().unwrap().completion, 3);
+    }
+}
@@ -0,0 +1,403 @@
+use std::{path::Path, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{compose::Question, context::Context};
+
+use super::{Answer, Answerer, AnswererKind, ReasoningStyle, Reply, Usage, Vcr};
+
+/// 合并多个模型的回答的方式
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Vote {
+    /// 每个模型按权重投一票
+    #[default]
+    Majority,
+    /// 每个模型的票再乘以答案的概率，有 logprobs 时按概率分给每个选项
+    Weighted,
+}
+
+/// 配置文件中的一个模型，没有设置的字段使用命令行参数的值
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Member {
+    /// 日志和统计中显示的名字，默认是模型名
+    pub name: Option<String>,
+    pub answerer: Option<AnswererKind>,
+    pub url: Option<String>,
+    pub model: Option<String>,
+    pub key: Option<String>,
+    /// 从环境变量中读取 API KEY，避免将 KEY 写在配置文件中
+    pub key_env: Option<String>,
+    pub cost_input: Option<f64>,
+    pub cost_output: Option<f64>,
+    pub thinking: Option<bool>,
+    /// 控制思考的参数的格式，同 --answer-reasoning
+    pub reasoning: Option<ReasoningStyle>,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+impl Member {
+    /// 用成员的配置覆盖命令行参数
+    fn context(&self, ctx: &Context) -> Context {
+        let mut ctx = ctx.clone();
+        ctx.ensemble = None;
+        if let Some(answerer) = self.answerer {
+            ctx.answerer = answerer;
+        }
+        if let Some(url) = &self.url {
+            ctx.api_url = url.clone();
+        }
+        if let Some(model) = &self.model {
+            ctx.api_model = model.clone();
+        }
+        if let Some(key) = &self.key {
+            ctx.api_key = key.clone();
+        }
+        if let Some(name) = &self.key_env {
+            ctx.api_key = std::env::var(name).unwrap_or_else(|_| panic!("{name} is not set"));
+        }
+        if let Some(cost) = self.cost_input {
+            ctx.api_cost_input = cost;
+        }
+        if let Some(cost) = self.cost_output {
+            ctx.api_cost_output = cost;
+        }
+        if let Some(thinking) = self.thinking {
+            ctx.answer_thinking = thinking;
+        }
+        if let Some(reasoning) = self.reasoning {
+            ctx.answer_reasoning = reasoning;
+        }
+        ctx
+    }
+}
+
+/// 同时询问多个模型，投票决定答案
+pub struct Ensemble {
+    vote: Vote,
+    members: Vec<Voter>,
+}
+
+struct Voter {
+    name: String,
+    weight: f64,
+    answerer: Box<dyn Answerer>,
+}
+
+impl Ensemble {
+    pub fn from_args(ctx: &Context, vcr: Option<Arc<Vcr>>) -> Self {
+        let path = ctx.ensemble.as_ref().unwrap();
+        let members = load_members(path);
+        let members = members
+            .iter()
+            .map(|member| {
+                let member_ctx = member.context(ctx);
+                assert!(
+                    !member_ctx.api_key.is_empty() || member_ctx.answerer.is_local(),
+                    "API key is required for ensemble member {:?}",
+                    member_ctx.api_model
+                );
+                Voter {
+                    name: member.name.clone().unwrap_or(member_ctx.api_model.clone()),
+                    weight: member.weight,
+                    answerer: super::backend_from_args(&member_ctx, vcr.clone()),
+                }
+            })
+            .collect();
+        Self::new(ctx.ensemble_vote, members)
+    }
+
+    fn new(vote: Vote, members: Vec<Voter>) -> Self {
+        assert!(!members.is_empty(), "ensemble has no members");
+        for member in &members {
+            log::debug!(
+                "ensemble member: {}, weight: {}",
+                member.name,
+                member.weight
+            );
+        }
+        Self { vote, members }
+    }
+}
+
+fn load_members(path: &Path) -> Vec<Member> {
+    let content = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()));
+    let members: Vec<Member> = serde_json::from_str(&content)
+        .unwrap_or_else(|e| panic!("failed to parse {}: {e}", path.display()));
+    for member in &members {
+        assert!(
+            member.weight.is_finite() && member.weight > 0.0,
+            "invalid weight {} in {}",
+            member.weight,
+            path.display()
+        );
+    }
+    members
+}
+
+impl Answerer for Ensemble {
+    fn answer(&mut self, question: &Question) -> Option<Reply> {
+        let replies: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = self
+                .members
+                .iter_mut()
+                .map(|member| s.spawn(|| member.answerer.answer(question)))
+                .collect();
+            // 认证失败或者额度用完时 `http::send` 会 panic，不能当作没有答案继续答题
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                })
+                .collect()
+        });
+        for (member, reply) in self.members.iter().zip(&replies) {
+            match reply {
+                Some(reply) => log::debug!(
+                    "{}: answer: {:?}, probability: {:?}",
+                    member.name,
+                    reply.answer,
+                    reply.probability()
+                ),
+                None => log::warn!("{}: no valid answer", member.name),
+            }
+        }
+        let ballots: Vec<_> = self
+            .members
+            .iter()
+            .zip(replies)
+            .map(|(member, reply)| (member.weight, reply))
+            .collect();
+        count_votes(self.vote, &ballots)
+    }
+
+    fn usage(&self) -> Usage {
+        self.members
+            .iter()
+            .map(|x| x.answerer.usage())
+            .fold(Usage::default(), |acc, x| acc + x)
+    }
+
+    fn usage_details(&self) -> Vec<(String, Usage)> {
+        self.members
+            .iter()
+            .map(|x| (x.name.clone(), x.answerer.usage()))
+            .collect()
+    }
+
+    fn log_summary(&self) {
+        for member in &self.members {
+            member.answerer.log_summary();
+        }
+    }
+}
+
+/// 按 `vote` 合并每个模型的权重和回答
+///
+/// 得票相同时依次比较答案的概率之和、按权重计算的票数，最后选择配置中靠前的模型的答案。
+/// 返回的回答中的 `distribution` 是每个选项的得票比例
+fn count_votes(vote: Vote, ballots: &[(f64, Option<Reply>)]) -> Option<Reply> {
+    let mut scores = [0f64; 4];
+    let mut probabilities = [0f64; 4];
+    let mut counts = [0f64; 4];
+    let mut first = [usize::MAX; 4];
+    for (i, (weight, reply)) in ballots.iter().enumerate() {
+        let Some(reply) = reply else {
+            continue;
+        };
+        let index = reply.answer as usize;
+        let probability = reply.probability().unwrap_or(1.0) as f64;
+        match (vote, reply.distribution) {
+            (Vote::Majority, _) => scores[index] += weight,
+            (Vote::Weighted, Some(distribution)) => {
+                for (score, p) in scores.iter_mut().zip(distribution) {
+                    *score += weight * p as f64;
+                }
+            }
+            (Vote::Weighted, None) => scores[index] += weight * probability,
+        }
+        probabilities[index] += probability;
+        counts[index] += weight;
+        first[index] = first[index].min(i);
+    }
+    let total: f64 = scores.iter().sum();
+    if first.iter().all(|x| *x == usize::MAX) || total <= 0.0 {
+        return None;
+    }
+
+    let key = |i: usize| (scores[i], probabilities[i], counts[i]);
+    let winner = (0..4)
+        .filter(|i| first[*i] != usize::MAX || scores[*i] > 0.0)
+        .max_by(|a, b| {
+            let (x, y) = (key(*a), key(*b));
+            x.0.total_cmp(&y.0)
+                .then(x.1.total_cmp(&y.1))
+                .then(x.2.total_cmp(&y.2))
+                .then(first[*b].cmp(&first[*a]))
+        })
+        .unwrap();
+    let distribution = scores.map(|x| (x / total) as f32);
+    Some(Reply {
+        distribution: Some(distribution),
+        ..Reply::from(Answer::from_index(winner))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        answerer::{
+            Endpoint, Multimodal, ReasoningEffort, mock::MockServer, multimodal::Reasoning,
+        },
+        logging,
+    };
+
+    use super::*;
+
+    fn reply(answer: Answer, confidence: Option<f32>) -> Option<Reply> {
+        Some(Reply {
+            confidence,
+            ..Reply::from(answer)
+        })
+    }
+
+    fn winner(vote: Vote, ballots: &[(f64, Option<Reply>)]) -> Answer {
+        count_votes(vote, ballots).unwrap().answer
+    }
+
+    #[test]
+    fn test_count_votes() {
+        let ballots = [
+            (1.0, reply(Answer::A, Some(0.9))),
+            (1.0, reply(Answer::B, Some(0.6))),
+            (1.0, reply(Answer::B, Some(0.5))),
+            (1.0, None),
+        ];
+        assert!(matches!(winner(Vote::Majority, &ballots), Answer::B));
+        // 0.9 < 0.6 + 0.5
+        assert!(matches!(winner(Vote::Weighted, &ballots), Answer::B));
+        let result = count_votes(Vote::Majority, &ballots).unwrap();
+        assert!((result.probability().unwrap() - 2.0 / 3.0).abs() < 1e-6);
+
+        let ballots = [
+            (1.0, reply(Answer::A, Some(0.95))),
+            (1.0, reply(Answer::B, Some(0.3))),
+            (1.0, reply(Answer::B, Some(0.3))),
+        ];
+        assert!(matches!(winner(Vote::Weighted, &ballots), Answer::A));
+
+        let ballots = [(1.0, reply(Answer::C, None)), (2.0, reply(Answer::D, None))];
+        assert!(matches!(winner(Vote::Majority, &ballots), Answer::D));
+
+        assert!(count_votes(Vote::Majority, &[(1.0, None)]).is_none());
+    }
+
+    #[test]
+    fn test_count_votes_tie() {
+        // 票数相同时选择概率之和更高的答案
+        let ballots = [
+            (1.0, reply(Answer::C, Some(0.4))),
+            (1.0, reply(Answer::A, Some(0.8))),
+        ];
+        assert!(matches!(winner(Vote::Majority, &ballots), Answer::A));
+
+        // 都相同时选择配置中靠前的模型的答案
+        let ballots = [(1.0, reply(Answer::C, None)), (1.0, reply(Answer::A, None))];
+        assert!(matches!(winner(Vote::Majority, &ballots), Answer::C));
+    }
+
+    #[test]
+    fn test_member_context() {
+        let members: Vec<Member> = serde_json::from_str(
+            r#"[
+                {"name": "claude", "answerer": "anthropic", "model": "claude-sonnet-4-5", "key": "sk-ant", "weight": 2},
+                {"answerer": "llama-cpp", "url": "http://localhost:8080/v1/chat/completions"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(members[0].weight, 2.0);
+        assert_eq!(members[1].weight, 1.0);
+
+        let ctx = <Context as clap::Parser>::parse_from([
+            "test",
+            "--api-url",
+            "https://example.com/v1/chat/completions",
+            "--api-model",
+            "doubao",
+            "--api-key",
+            "sk-default",
+            "--ensemble",
+            "ensemble.json",
+        ]);
+        let claude = members[0].context(&ctx);
+        assert!(matches!(claude.answerer, AnswererKind::Anthropic));
+        assert_eq!(claude.api_model, "claude-sonnet-4-5");
+        assert_eq!(claude.api_key, "sk-ant");
+        assert_eq!(claude.api_url, ctx.api_url);
+        assert!(claude.ensemble.is_none());
+        let local = members[1].context(&ctx);
+        assert!(matches!(local.answerer, AnswererKind::LlamaCpp));
+        assert_eq!(local.api_model, "doubao");
+    }
+
+    #[test]
+    fn test_load_members() {
+        let path = std::env::temp_dir().join(format!("ensemble-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"[{"answerer": "openai", "reasoning": "enable-thinking"}]"#,
+        )
+        .unwrap();
+        let members = load_members(&path);
+        assert!(matches!(members[0].answerer, Some(AnswererKind::OpenAi)));
+        assert_eq!(members[0].reasoning, Some(ReasoningStyle::EnableThinking));
+
+        std::fs::write(&path, r#"[{"weight": 0}]"#).unwrap();
+        let zero = std::panic::catch_unwind(|| load_members(&path));
+        std::fs::write(&path, r#"[{"answerer": "gpt"}]"#).unwrap();
+        let unknown = std::panic::catch_unwind(|| load_members(&path));
+        std::fs::remove_file(&path).unwrap();
+        assert!(zero.is_err());
+        assert!(unknown.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "401")]
+    fn test_fatal_member() {
+        logging::init_for_test();
+        let ok = r#"{"choices": [{"message": {"content": "B"}}]}"#;
+        let good = MockServer::start(vec![(200, ok.to_owned())]);
+        let bad = MockServer::start(vec![(
+            401,
+            r#"{"error": {"message": "invalid key"}}"#.to_owned(),
+        )]);
+        let member = |url: String| {
+            let reasoning = Reasoning {
+                style: ReasoningStyle::None,
+                thinking: false,
+                effort: ReasoningEffort::Medium,
+                budget: 1024,
+            };
+            let answerer = Multimodal::new(Endpoint::for_test(url), reasoning, false, false);
+            Voter {
+                name: "member".to_owned(),
+                weight: 1.0,
+                answerer: Box::new(answerer),
+            }
+        };
+        let members = vec![
+            member(good.url("/v1/chat/completions")),
+            member(bad.url("/v1/chat/completions")),
+        ];
+        let mut ensemble = Ensemble::new(Vote::Majority, members);
+        ensemble.answer(&Question::for_test());
+    }
+}
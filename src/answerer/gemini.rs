@@ -0,0 +1,393 @@
+use std::sync::Arc;
+
+use reqwest::blocking::{Client, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{compose::Question, context::Context};
+
+use super::{
+    Answerer, Endpoint, Reply, ResponseError, Turn, Usage, Vcr, new_headers, parse_body,
+    parse_reply, reply_schema, turns,
+};
+
+/// Gemini 的 `generateContent` 接口
+pub struct Gemini {
+    /// `url` 是 API 的根地址，例如 `https://generativelanguage.googleapis.com/v1beta`
+    endpoint: Endpoint,
+    /// 通过 `?key=` 传递 API KEY，否则使用 `x-goog-api-key` 请求头
+    key_in_query: bool,
+    client: Client,
+    thinking_budget: Option<u32>,
+    structured: bool,
+
+    input_tokens: u64,
+    output_tokens: u64,
+    reasoning_tokens: u64,
+}
+
+impl Gemini {
+    pub fn from_args(ctx: &Context, vcr: Option<Arc<Vcr>>) -> Self {
+        let thinking_budget = ctx.answer_thinking.then_some(ctx.answer_thinking_budget);
+        Self::new(
+            Endpoint::from_args(ctx, vcr),
+            ctx.gemini_key_in_query,
+            thinking_budget,
+            ctx.answer_structured,
+        )
+    }
+
+    pub fn new(
+        endpoint: Endpoint,
+        key_in_query: bool,
+        thinking_budget: Option<u32>,
+        structured: bool,
+    ) -> Self {
+        Self {
+            endpoint,
+            key_in_query,
+            client: Client::new(),
+            thinking_budget,
+            structured,
+            input_tokens: 0,
+            output_tokens: 0,
+            reasoning_tokens: 0,
+        }
+    }
+
+    /// 完整的请求地址，`url` 中已经包含 `:generateContent` 时直接使用
+    fn request_url(&self) -> String {
+        let endpoint = &self.endpoint;
+        let mut url = if endpoint.url.contains(":generateContent") {
+            endpoint.url.clone()
+        } else {
+            format!(
+                "{}/models/{}:generateContent",
+                endpoint.url.trim_end_matches('/'),
+                endpoint.model
+            )
+        };
+        if self.key_in_query {
+            url.push(if url.contains('?') { '&' } else { '?' });
+            url.push_str("key=");
+            url.push_str(&endpoint.key);
+        }
+        url
+    }
+
+    fn body(&self, question: &Question) -> serde_json::Value {
+        // Gemini 中模型的角色是 model
+        let contents: Vec<_> = turns(question, self.structured)
+            .into_iter()
+            .map(|turn| match turn {
+                Turn::User { images, text } => {
+                    let mut parts: Vec<_> = images
+                        .into_iter()
+                        .map(|img| {
+                            let image = self.endpoint.image.encode(img);
+                            json!({
+                                "inline_data": {
+                                    "mime_type": image.mime,
+                                    "data": image.data,
+                                },
+                            })
+                        })
+                        .collect();
+                    parts.push(json!({ "text": text }));
+                    json!({
+                        "role": "user",
+                        "parts": parts,
+                    })
+                }
+                Turn::Assistant(text) => json!({
+                    "role": "model",
+                    "parts": [{ "text": text }],
+                }),
+            })
+            .collect();
+        let mut body = json!({
+            "contents": contents,
+            "generationConfig": {},
+        });
+        // 2.5 Flash 等模型默认会思考，没有开启思考时发送 0 关闭
+        body["generationConfig"]["thinkingConfig"] =
+            json!({ "thinkingBudget": self.thinking_budget.unwrap_or(0) });
+        if let Some(system) = &question.prompt.system {
+            body["systemInstruction"] = json!({ "parts": [{ "text": system }] });
+        }
+        if self.structured {
+            // responseSchema 不支持 additionalProperties
+            let mut schema = reply_schema(question);
+            schema
+                .as_object_mut()
+                .unwrap()
+                .remove("additionalProperties");
+            body["generationConfig"]["responseMimeType"] = json!("application/json");
+            body["generationConfig"]["responseSchema"] = schema;
+        }
+        body
+    }
+
+    fn post(&self, question: &Question) -> Option<Response> {
+        let mut headers = vec![("Content-Type", "application/json")];
+        if !self.key_in_query {
+            headers.push(("x-goog-api-key", &self.endpoint.key));
+        }
+        let headers = new_headers(&headers);
+        let body = self.body(question).to_string();
+        log::debug!("request {}", self.endpoint.url);
+        let resp = self.endpoint.send(|| {
+            self.client
+                .post(self.request_url())
+                .headers(headers.clone())
+                .body(body.clone())
+                .timeout(self.endpoint.timeout)
+        })?;
+        Some(resp)
+    }
+}
+
+/// `generateContent` 的响应
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct GenerateResponse {
+    candidates: Vec<Candidate>,
+    usage_metadata: UsageMetadata,
+    prompt_feedback: Option<PromptFeedback>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Candidate {
+    /// 被安全设置拦截时没有 content
+    content: Option<CandidateContent>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CandidateContent {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Part {
+    text: Option<String>,
+    /// 思考的摘要
+    thought: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct UsageMetadata {
+    prompt_token_count: u64,
+    /// 不包括思考
+    candidates_token_count: u64,
+    thoughts_token_count: u64,
+}
+
+impl UsageMetadata {
+    /// 输入、输出和思考的 token 数，输出包括思考
+    fn tokens(&self) -> (u64, u64, u64) {
+        (
+            self.prompt_token_count,
+            self.candidates_token_count + self.thoughts_token_count,
+            self.thoughts_token_count,
+        )
+    }
+}
+
+/// 返回回答的文字
+fn parse_response(resp: &GenerateResponse) -> Result<String, ResponseError> {
+    let Some(candidate) = resp.candidates.first() else {
+        let reason = resp
+            .prompt_feedback
+            .as_ref()
+            .and_then(|x| x.block_reason.as_deref())
+            .unwrap_or("unknown");
+        return Err(ResponseError::Empty(format!(
+            "no candidates, block reason: {reason}"
+        )));
+    };
+    let content = candidate.content.as_ref().ok_or_else(|| {
+        let reason = candidate.finish_reason.as_deref().unwrap_or("unknown");
+        ResponseError::Empty(format!("no content, finish reason: {reason}"))
+    })?;
+    let message: String = content
+        .parts
+        .iter()
+        .filter(|x| !x.thought)
+        .filter_map(|x| x.text.as_deref())
+        .collect();
+    Ok(message)
+}
+
+impl Answerer for Gemini {
+    fn answer(&mut self, question: &Question) -> Option<Reply> {
+        let resp: GenerateResponse = parse_body(self.post(question)?)
+            .inspect_err(|e| log::warn!("{e}"))
+            .ok()?;
+        // 被拦截或者没有内容时输入的 token 仍然计费
+        let (input_tokens, output_tokens, reasoning_tokens) = resp.usage_metadata.tokens();
+        log::debug!(
+            "tokens: input: {input_tokens}, output: {output_tokens}, reasoning: {reasoning_tokens}, total: {}",
+            input_tokens + output_tokens
+        );
+        self.input_tokens += input_tokens;
+        self.output_tokens += output_tokens;
+        self.reasoning_tokens += reasoning_tokens;
+
+        let message = parse_response(&resp)
+            .inspect_err(|e| log::warn!("{e}"))
+            .ok()?;
+        log::debug!("raw answer: {}", message);
+        parse_reply(&message)
+    }
+
+    fn usage(&self) -> Usage {
+        Usage {
+            reasoning_tokens: self.reasoning_tokens,
+            ..self
+                .endpoint
+                .cost
+                .usage(self.input_tokens, self.output_tokens)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use crate::{
+        answerer::{Answer, mock::MockServer},
+        compose::ImageLayout,
+        prompt::Template,
+    };
+
+    use super::*;
+
+    fn parse(json: &str) -> GenerateResponse {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn gemini(url: &str, key_in_query: bool) -> Gemini {
+        gemini_with(url, key_in_query, false)
+    }
+
+    fn gemini_with(url: &str, key_in_query: bool, structured: bool) -> Gemini {
+        let endpoint = Endpoint {
+            model: "gemini-2.5-flash".to_owned(),
+            key: "AIza-test".to_owned(),
+            ..Endpoint::for_test(url.to_owned())
+        };
+        Gemini::new(endpoint, key_in_query, None, structured)
+    }
+
+    #[test]
+    fn test_request_url() {
+        let base = "https://generativelanguage.googleapis.com/v1beta/";
+        assert_eq!(
+            gemini(base, false).request_url(),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:generateContent"
+        );
+        assert_eq!(
+            gemini(base, true).request_url(),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:generateContent?key=AIza-test"
+        );
+        let full = "https://proxy.example.com/v1/models/gemini-2.5-pro:generateContent?alt=json";
+        assert_eq!(
+            gemini(full, true).request_url(),
+            format!("{full}&key=AIza-test")
+        );
+    }
+
+    #[test]
+    fn test_body() {
+        let mut question = Question {
+            layout: ImageLayout::Parts,
+            images: vec![RgbImage::new(8, 8), RgbImage::new(8, 4)],
+            ..Question::for_test()
+        };
+        question.prompt = Template::builtin(String::new()).render(&question, false);
+        question.prompt.system = Some("你是一个答题助手。".to_owned());
+        let body = gemini("http://localhost", false).body(&question);
+        let parts = body["contents"][0]["parts"].as_array().unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0]["inline_data"]["mime_type"], "image/jpeg");
+        assert!(parts[2]["text"].as_str().unwrap().contains("选项"));
+        assert_eq!(
+            body["systemInstruction"]["parts"][0]["text"],
+            "你是一个答题助手。"
+        );
+        assert_eq!(
+            body["generationConfig"]["thinkingConfig"]["thinkingBudget"],
+            0
+        );
+        assert!(body["generationConfig"].get("responseSchema").is_none());
+
+        let mut thinking = gemini("http://localhost", false);
+        thinking.thinking_budget = Some(1024);
+        let body = thinking.body(&question);
+        assert_eq!(
+            body["generationConfig"]["thinkingConfig"]["thinkingBudget"],
+            1024
+        );
+
+        let body = gemini_with("http://localhost", false, true).body(&question);
+        let config = &body["generationConfig"];
+        assert_eq!(config["responseMimeType"], "application/json");
+        assert_eq!(config["responseSchema"]["required"][0], "answer");
+        assert!(
+            config["responseSchema"]
+                .get("additionalProperties")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let resp = parse(include_str!("../../tests/fixtures/gemini/answer.json"));
+        let message = parse_response(&resp).unwrap();
+        assert_eq!(message, "C");
+        assert_eq!(resp.usage_metadata.tokens(), (1102, 1, 0));
+        assert!(matches!(parse_reply(&message).unwrap().answer, Answer::C));
+    }
+
+    #[test]
+    fn test_parse_response_thinking() {
+        let resp = parse(include_str!("../../tests/fixtures/gemini/thinking.json"));
+        let message = parse_response(&resp).unwrap();
+        assert_eq!(message, "答案：D");
+        assert_eq!(resp.usage_metadata.tokens(), (1102, 4 + 331, 331));
+        assert!(matches!(parse_reply(&message).unwrap().answer, Answer::D));
+    }
+
+    #[test]
+    fn test_parse_response_blocked() {
+        let resp = parse(
+            r#"{"promptFeedback": {"blockReason": "SAFETY"}, "usageMetadata": {"promptTokenCount": 1102}}"#,
+        );
+        let error = parse_response(&resp).unwrap_err().to_string();
+        assert!(error.contains("SAFETY"), "{error}");
+        let resp = parse(r#"{"candidates": [{"finishReason": "RECITATION"}]}"#);
+        assert!(parse_response(&resp).is_err());
+    }
+
+    #[test]
+    fn test_answer_blocked_usage() {
+        let resp = r#"{"promptFeedback": {"blockReason": "SAFETY"}, "usageMetadata": {"promptTokenCount": 1102}}"#;
+        let server = MockServer::start(vec![(200, resp.to_owned())]);
+        let mut gemini = gemini(&server.url("/v1beta"), false);
+        let question = Question::for_test();
+        assert!(gemini.answer(&question).is_none());
+        assert_eq!(gemini.usage().input_tokens, 1102);
+    }
+}
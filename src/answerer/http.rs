@@ -0,0 +1,306 @@
+//! 发送请求，按失败的原因决定是否重试
+
+use std::time::{Duration, Instant};
+
+use rand::RngExt;
+use reqwest::{
+    StatusCode,
+    blocking::{RequestBuilder, Response},
+    header::HeaderMap,
+};
+
+/// 重试的间隔和总时间
+#[derive(Clone, Copy, Debug)]
+pub struct Retry {
+    /// 第一次重试前的等待时间，之后每次翻倍
+    pub base: Duration,
+    /// 每次等待的最长时间
+    pub max_delay: Duration,
+    /// 从第一次请求开始的总时间，超过后不再重试
+    pub budget: Duration,
+}
+
+impl Retry {
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            base: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            budget,
+        }
+    }
+
+    /// 第 `attempt` 次重试前的等待时间，在指数退避的一半到全部之间随机
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay);
+        delay.mul_f64(rand::rng().random_range(0.5..=1.0))
+    }
+}
+
+/// 请求失败的原因
+#[derive(Debug)]
+pub(super) enum HttpError {
+    Timeout,
+    Connect(String),
+    /// 429，服务要求的等待时间来自 `Retry-After`
+    RateLimited(Option<Duration>),
+    /// 5xx 以及 408
+    Server(StatusCode),
+    /// 401 和 403，API KEY 无效或者没有权限
+    Auth(StatusCode, String),
+    /// 余额不足或者用量超过限额
+    Quota(StatusCode, String),
+    /// 其他 4xx，重试也不会成功
+    Client(StatusCode, String),
+    Other(String),
+}
+
+impl HttpError {
+    fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Timeout | Self::Connect(_) | Self::RateLimited(_) | Self::Server(_)
+        )
+    }
+
+    /// 无法通过重试恢复，继续答题也没有意义
+    fn is_fatal(&self) -> bool {
+        matches!(self, Self::Auth(..) | Self::Quota(..))
+    }
+
+    /// 去掉错误中的 URL，`--gemini-key-in-query` 时其中包含 API key
+    fn from_reqwest(e: reqwest::Error) -> Self {
+        let e = e.without_url();
+        if e.is_timeout() {
+            Self::Timeout
+        } else if e.is_connect() {
+            Self::Connect(e.to_string())
+        } else {
+            Self::Other(e.to_string())
+        }
+    }
+
+    /// 按状态码分类失败的响应
+    fn from_response(resp: Response) -> Result<Response, Self> {
+        let status = resp.status();
+        if status.is_success() {
+            return Ok(resp);
+        }
+        let retry_after = retry_after(resp.headers());
+        let body = resp.text().unwrap_or_default();
+        // 有的服务余额不足时也返回 429，只按明确的错误码判断，
+        // 普通的限流的提示中也可能有 quota，例如 Gemini 的每分钟请求数
+        let quota = error_codes(&body)
+            .iter()
+            .any(|x| QUOTA_CODES.contains(&x.as_str()));
+        Err(match status.as_u16() {
+            401 | 403 => Self::Auth(status, body),
+            402 => Self::Quota(status, body),
+            429 if quota => Self::Quota(status, body),
+            429 => Self::RateLimited(retry_after),
+            408 => Self::Server(status),
+            _ if status.is_server_error() => Self::Server(status),
+            _ => Self::Client(status, body),
+        })
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "request timed out"),
+            Self::Connect(e) => write!(f, "connection failed: {e}"),
+            Self::RateLimited(Some(after)) => write!(f, "rate limited, retry after {after:?}"),
+            Self::RateLimited(None) => write!(f, "rate limited"),
+            Self::Server(status) => write!(f, "server error: {status}"),
+            Self::Auth(status, body) => {
+                write!(
+                    f,
+                    "authentication failed: {status}, check --api-key: {body}"
+                )
+            }
+            Self::Quota(status, body) => {
+                write!(
+                    f,
+                    "quota exceeded: {status}, check the account balance: {body}"
+                )
+            }
+            Self::Client(status, body) => write!(f, "request rejected: {status}: {body}"),
+            Self::Other(e) => write!(f, "request failed: {e}"),
+        }
+    }
+}
+
+/// 余额不足或者欠费的错误码：OpenAI、阿里云百炼、火山方舟、智谱
+const QUOTA_CODES: &[&str] = &[
+    "insufficient_quota",
+    "billing_hard_limit_reached",
+    "billing_not_active",
+    "Arrearage",
+    "AccountOverdueError",
+    "1113",
+];
+
+/// 错误响应中 `error.code` 和 `error.type` 的值，有的服务直接放在最外层
+fn error_codes(body: &str) -> Vec<String> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(body) else {
+        return vec![];
+    };
+    let error = value.get("error").unwrap_or(&value);
+    ["code", "type"]
+        .iter()
+        .filter_map(|key| match &error[key] {
+            serde_json::Value::String(x) => Some(x.clone()),
+            serde_json::Value::Number(x) => Some(x.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `Retry-After` 可以是秒数或者 HTTP 日期，OpenAI 还会返回毫秒数的 `retry-after-ms`
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name| headers.get(name)?.to_str().ok().map(str::trim);
+    if let Some(ms) = header("retry-after-ms").and_then(|x| x.parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+    let value = header("retry-after")?;
+    if let Ok(secs) = value.parse::<f64>() {
+        return Some(Duration::from_secs_f64(secs.max(0.0)));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let secs = (date.timestamp_millis() - chrono::Utc::now().timestamp_millis()).max(0);
+    Some(Duration::from_millis(secs as u64))
+}
+
+/// 发送请求，暂时的失败按 `retry` 重试，API KEY 无效或者余额不足时直接退出
+///
+/// `request` 每次重试时重新构造请求
+pub(super) fn send(retry: &Retry, request: impl Fn() -> RequestBuilder) -> Option<Response> {
+    let start = Instant::now();
+    let mut attempt = 0;
+    loop {
+        let result = request()
+            .send()
+            .map_err(HttpError::from_reqwest)
+            .and_then(HttpError::from_response);
+        let error = match result {
+            Ok(resp) => return Some(resp),
+            Err(error) => error,
+        };
+        assert!(!error.is_fatal(), "{error}");
+        if !error.is_transient() {
+            log::warn!("{error}");
+            return None;
+        }
+        let delay = match error {
+            HttpError::RateLimited(Some(after)) => after,
+            _ => retry.backoff(attempt),
+        };
+        if start.elapsed() + delay > retry.budget {
+            log::warn!(
+                "{error}, give up after {} attempts in {:?}",
+                attempt + 1,
+                start.elapsed()
+            );
+            return None;
+        }
+        attempt += 1;
+        log::info!("{error}, retry {attempt} after {delay:?}");
+        std::thread::sleep(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::blocking::Client;
+
+    use crate::answerer::mock::MockServer;
+
+    use super::*;
+
+    fn retry() -> Retry {
+        Retry {
+            base: Duration::from_millis(10),
+            max_delay: Duration::from_millis(40),
+            budget: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_send_retry() {
+        let server = MockServer::start_with_headers(vec![
+            (503, vec![], String::new()),
+            (429, vec![("Retry-After", "0")], "{}".to_owned()),
+            (200, vec![], "ok".to_owned()),
+        ]);
+        let client = Client::new();
+        let url = server.url("/");
+        let resp = send(&retry(), || client.post(&url)).unwrap();
+        assert_eq!(resp.text().unwrap(), "ok");
+        assert_eq!(server.requests().len(), 3);
+
+        // 其他 4xx 不重试
+        let server = MockServer::start(vec![(400, "bad request".to_owned())]);
+        let url = server.url("/");
+        assert!(send(&retry(), || client.post(&url)).is_none());
+
+        // 等待时间超过总时间时不再重试
+        let server = MockServer::start(vec![(502, String::new()); 2]);
+        let url = server.url("/");
+        let retry = Retry {
+            budget: Duration::from_millis(1),
+            ..retry()
+        };
+        assert!(send(&retry, || client.post(&url)).is_none());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "quota exceeded")]
+    fn test_send_quota() {
+        let body = r#"{"error": {"code": "insufficient_quota"}}"#;
+        let server = MockServer::start(vec![(429, body.to_owned())]);
+        let client = Client::new();
+        let url = server.url("/");
+        send(&retry(), || client.post(&url));
+    }
+
+    #[test]
+    fn test_send_gemini_rate_limit() {
+        // Gemini 超过每分钟请求数时的 429 中也有 quota，但是可以重试
+        let body = r#"{"error": {"code": 429, "message": "You exceeded your current quota, please check your plan and billing details. Quota exceeded for metric: generativelanguage.googleapis.com/generate_content_free_tier_requests, limit: 10. Please retry in 20s.", "status": "RESOURCE_EXHAUSTED"}}"#;
+        let server = MockServer::start_with_headers(vec![
+            (429, vec![("Retry-After", "0")], body.to_owned()),
+            (200, vec![], "ok".to_owned()),
+        ]);
+        let client = Client::new();
+        let url = server.url("/");
+        let resp = send(&retry(), || client.post(&url)).unwrap();
+        assert_eq!(resp.text().unwrap(), "ok");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_error_without_url() {
+        // 端口 1 上没有服务，连接失败
+        let e = Client::new()
+            .get("http://127.0.0.1:1/v1beta?key=secret")
+            .send()
+            .unwrap_err();
+        let error = HttpError::from_reqwest(e);
+        assert!(matches!(error, HttpError::Connect(_)), "{error}");
+        assert!(!error.to_string().contains("secret"), "{error}");
+    }
+
+    #[test]
+    fn test_backoff() {
+        let retry = retry();
+        for attempt in 0..10 {
+            let delay = retry.backoff(attempt);
+            let full = (retry.base * (1 << attempt)).min(retry.max_delay);
+            assert!(delay >= full / 2 && delay <= full);
+        }
+    }
+}
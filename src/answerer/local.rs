@@ -0,0 +1,317 @@
+use std::sync::Arc;
+
+use reqwest::blocking::{Client, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{compose::Question, context::Context};
+
+use super::{
+    Answerer, Cost, Endpoint, Reply, ResponseError, Turn, Usage, Vcr,
+    completion::{ChatCompletion, Tokens},
+    new_headers, openai_messages, parse_body, parse_reply, reply_schema, request_logprobs,
+    response_format, turns, with_logprobs,
+};
+
+/// 本地模型服务的类型
+#[derive(Clone, Copy, Debug)]
+pub enum LocalServer {
+    /// Ollama 的 `/api/chat`，图片是 base64 的数组
+    Ollama,
+    /// llama.cpp server 的 `/v1/chat/completions`
+    LlamaCpp,
+}
+
+/// 本地运行的模型，没有 token 费用
+pub struct Local {
+    endpoint: Endpoint,
+    server: LocalServer,
+    client: Client,
+    thinking: bool,
+    structured: bool,
+    /// 仅 llama.cpp 使用
+    logprobs: bool,
+
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl Local {
+    pub fn from_args(ctx: &Context, server: LocalServer, vcr: Option<Arc<Vcr>>) -> Self {
+        Self::new(
+            Endpoint::from_args(ctx, vcr),
+            server,
+            ctx.answer_thinking,
+            ctx.answer_structured,
+            ctx.answer_logprobs,
+        )
+    }
+
+    pub fn new(
+        endpoint: Endpoint,
+        server: LocalServer,
+        thinking: bool,
+        structured: bool,
+        logprobs: bool,
+    ) -> Self {
+        let endpoint = Endpoint {
+            cost: Cost::default(),
+            ..endpoint
+        };
+        Self {
+            endpoint,
+            server,
+            client: Client::new(),
+            thinking,
+            structured,
+            logprobs,
+            input_tokens: 0,
+            output_tokens: 0,
+        }
+    }
+
+    fn body(&self, question: &Question) -> serde_json::Value {
+        match self.server {
+            LocalServer::Ollama => {
+                let mut messages = vec![];
+                if let Some(system) = &question.prompt.system {
+                    messages.push(json!({
+                        "role": "system",
+                        "content": system,
+                    }));
+                }
+                for turn in turns(question, self.structured) {
+                    messages.push(match turn {
+                        Turn::User { images, text } => json!({
+                            "role": "user",
+                            "content": text,
+                            "images": images
+                                .into_iter()
+                                .map(|img| self.endpoint.image.encode(img).data)
+                                .collect::<Vec<_>>(),
+                        }),
+                        Turn::Assistant(text) => json!({
+                            "role": "assistant",
+                            "content": text,
+                        }),
+                    });
+                }
+                let mut body = json!({
+                    "model": self.endpoint.model,
+                    "messages": messages,
+                    "stream": false,
+                });
+                // 不支持思考的模型收到 think 参数会报错，只在开启时发送
+                if self.thinking {
+                    body["think"] = json!(true);
+                }
+                if self.structured {
+                    body["format"] = reply_schema(question);
+                }
+                body
+            }
+            LocalServer::LlamaCpp => {
+                let mut body = json!({
+                    "model": self.endpoint.model,
+                    "messages": openai_messages(question, self.structured, &self.endpoint.image),
+                    "chat_template_kwargs": {
+                        "enable_thinking": self.thinking,
+                    },
+                });
+                if self.structured {
+                    body["response_format"] = response_format(question);
+                }
+                if self.logprobs {
+                    request_logprobs(&mut body);
+                }
+                body
+            }
+        }
+    }
+
+    fn post(&self, question: &Question) -> Option<Response> {
+        let mut headers = vec![("Content-Type", "application/json".to_owned())];
+        if !self.endpoint.key.is_empty() {
+            headers.push(("Authorization", format!("Bearer {}", self.endpoint.key)));
+        }
+        let headers: Vec<_> = headers.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let body = self.body(question).to_string();
+        log::debug!("request {}", self.endpoint.url);
+        let resp = self.endpoint.send(|| {
+            self.client
+                .post(&self.endpoint.url)
+                .headers(new_headers(&headers))
+                .body(body.clone())
+                .timeout(self.endpoint.timeout)
+        })?;
+        Some(resp)
+    }
+}
+
+/// Ollama `/api/chat` 的响应
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct OllamaResponse {
+    message: Option<OllamaMessage>,
+    /// 命中 prompt 缓存时没有
+    prompt_eval_count: u64,
+    eval_count: u64,
+    done_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct OllamaMessage {
+    content: String,
+}
+
+/// 返回回答的文字、用量以及 llama.cpp 返回的 logprobs
+fn parse_response(
+    server: LocalServer,
+    resp: Response,
+) -> Result<(String, Tokens, Option<serde_json::Value>), ResponseError> {
+    match server {
+        LocalServer::Ollama => {
+            let resp: OllamaResponse = parse_body(resp)?;
+            let message = resp.message.ok_or_else(|| {
+                let reason = resp.done_reason.as_deref().unwrap_or("unknown");
+                ResponseError::Empty(format!("no message, done reason: {reason}"))
+            })?;
+            let tokens = Tokens {
+                prompt: resp.prompt_eval_count,
+                completion: resp.eval_count,
+                reasoning: 0,
+            };
+            Ok((message.content, tokens, None))
+        }
+        LocalServer::LlamaCpp => {
+            let resp: ChatCompletion = parse_body(resp)?;
+            let (message, logprobs) = resp.reply()?;
+            let tokens = resp.tokens().unwrap_or_default();
+            Ok((message, tokens, logprobs.cloned()))
+        }
+    }
+}
+
+impl Answerer for Local {
+    fn answer(&mut self, question: &Question) -> Option<Reply> {
+        let resp = self.post(question)?;
+        let (message, tokens, logprobs) = parse_response(self.server, resp)
+            .inspect_err(|e| log::warn!("{e}"))
+            .ok()?;
+        let (input_tokens, output_tokens) = (tokens.prompt, tokens.completion);
+        log::debug!(
+            "tokens: input: {input_tokens}, output: {output_tokens}, total: {}",
+            input_tokens + output_tokens
+        );
+        self.input_tokens += input_tokens;
+        self.output_tokens += output_tokens;
+
+        log::debug!("raw answer: {}", message);
+        let reply = parse_reply(&message);
+        match self.server {
+            LocalServer::LlamaCpp if self.logprobs => with_logprobs(reply, logprobs.as_ref()),
+            _ => reply,
+        }
+    }
+
+    fn usage(&self) -> Usage {
+        self.endpoint
+            .cost
+            .usage(self.input_tokens, self.output_tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        answerer::{Answer, mock::MockServer},
+        logging,
+    };
+
+    use super::*;
+
+    fn endpoint(url: String) -> Endpoint {
+        Endpoint {
+            key: String::new(),
+            cost: Cost {
+                input: 1.0,
+                output: 1.0,
+            },
+            ..Endpoint::for_test(url)
+        }
+    }
+
+    #[test]
+    fn test_ollama() {
+        logging::init_for_test();
+        let resp = json!({
+            "model": "qwen2.5vl:7b",
+            "created_at": "2025-09-01T08:00:00.000000Z",
+            "message": {"role": "assistant", "content": "A"},
+            "done": true,
+            "done_reason": "stop",
+            "total_duration": 48_000_000_000u64,
+            "prompt_eval_count": 745,
+            "eval_count": 2,
+        });
+        let server = MockServer::start(vec![(200, resp.to_string())]);
+        let mut answerer = Local::new(
+            endpoint(server.url("/api/chat")),
+            LocalServer::Ollama,
+            false,
+            false,
+            false,
+        );
+
+        let reply = answerer.answer(&Question::for_test()).unwrap();
+        assert!(matches!(reply.answer, Answer::A));
+        let usage = answerer.usage();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (745, 2));
+        assert_eq!(usage.cost, 0.0);
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/api/chat");
+        assert_eq!(request.header("Authorization"), None);
+        let body = request.json();
+        assert_eq!(body["stream"], false);
+        assert!(body.get("think").is_none());
+        assert!(body.get("format").is_none());
+        let images = body["messages"][0]["images"].as_array().unwrap();
+        assert_eq!(images.len(), 1);
+        assert!(!images[0].as_str().unwrap().starts_with("data:"));
+    }
+
+    #[test]
+    fn test_llama_cpp() {
+        logging::init_for_test();
+        let resp = json!({
+            "choices": [
+                {"index": 0, "finish_reason": "stop", "message": {"role": "assistant", "content": "{\"answer\": \"D\", \"confidence\": 0.7}"}},
+            ],
+            "model": "gemma-3-4b-it",
+            "object": "chat.completion",
+            "timings": {"prompt_n": 530, "predicted_n": 3},
+        });
+        let server = MockServer::start(vec![(200, resp.to_string())]);
+        let mut answerer = Local::new(
+            endpoint(server.url("/v1/chat/completions")),
+            LocalServer::LlamaCpp,
+            true,
+            true,
+            false,
+        );
+
+        let reply = answerer.answer(&Question::for_test()).unwrap();
+        assert!(matches!(reply.answer, Answer::D));
+        assert_eq!(reply.confidence, Some(0.7));
+        let usage = answerer.usage();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (530, 3));
+        assert_eq!(usage.cost, 0.0);
+
+        let body = server.requests()[0].json();
+        assert_eq!(body["chat_template_kwargs"]["enable_thinking"], true);
+        assert_eq!(body["messages"][0]["content"][0]["type"], "image_url");
+        assert_eq!(body["response_format"]["type"], "json_schema");
+    }
+}
@@ -0,0 +1,73 @@
+//! 测试用的 HTTP 服务
+
+use std::{
+    io::{BufReader, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+use crate::mock_llm::{Request, read_request};
+
+/// 响应中额外的响应头
+pub(crate) type Headers = Vec<(&'static str, &'static str)>;
+
+/// 按顺序返回预设的响应，并记录收到的请求
+pub(crate) struct MockServer {
+    addr: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    /// `responses` 是状态码和响应体，服务在返回所有响应之后退出
+    pub(crate) fn start(responses: Vec<(u16, String)>) -> Self {
+        let responses = responses
+            .into_iter()
+            .map(|(status, body)| (status, vec![], body))
+            .collect();
+        Self::start_with_headers(responses)
+    }
+
+    /// 同 `start`，每个响应还可以带额外的响应头
+    pub(crate) fn start_with_headers(responses: Vec<(u16, Headers, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(Mutex::new(vec![]));
+        {
+            let requests = requests.clone();
+            std::thread::spawn(move || {
+                for (status, headers, body) in responses {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream);
+                    let request = read_request(&mut reader).unwrap();
+                    requests.lock().unwrap().push(request);
+                    let mut stream = reader.into_inner();
+                    let headers: String = headers
+                        .iter()
+                        .map(|(key, value)| format!("{key}: {value}\r\n"))
+                        .collect();
+                    write!(
+                        stream,
+                        "HTTP/1.1 {status} MOCK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{headers}\r\n{body}",
+                        body.len()
+                    )
+                    .unwrap();
+                }
+            });
+        }
+        Self { addr, requests }
+    }
+
+    pub(crate) fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub(crate) fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Request {
+    pub(crate) fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
@@ -0,0 +1,721 @@
+mod anthropic;
+mod calibration;
+mod cascade;
+mod completion;
+mod ensemble;
+mod gemini;
+mod http;
+mod local;
+#[cfg(test)]
+mod mock;
+mod multimodal;
+mod stream;
+mod upload;
+mod vcr;
+mod verify;
+
+use std::{sync::Arc, time::Duration};
+
+use image::RgbImage;
+use rand::RngExt;
+use reqwest::{
+    blocking::{RequestBuilder, Response},
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
+use serde_json::json;
+
+use crate::{
+    compose::{Question, QuestionText},
+    context::Context,
+    few_shot::ExampleContent,
+    prompt::VERIFY_PROMPT,
+};
+
+pub use anthropic::Anthropic;
+pub use calibration::Calibration;
+pub use cascade::Cascade;
+pub use ensemble::{Ensemble, Vote};
+pub use gemini::Gemini;
+pub use http::Retry;
+pub use local::{Local, LocalServer};
+pub use multimodal::{Multimodal, ReasoningEffort, ReasoningStyle};
+pub use upload::{ImageOptions, UploadFormat};
+pub use vcr::Vcr;
+pub use verify::Verify;
+
+/// 回答题目的后端
+pub trait Answerer: Send {
+    fn answer(&mut self, question: &Question) -> Option<Reply>;
+    /// 累计的 token 用量
+    fn usage(&self) -> Usage;
+    /// 由多个模型组成时每个模型各自的用量
+    fn usage_details(&self) -> Vec<(String, Usage)> {
+        vec![]
+    }
+    /// 结束时输出的统计
+    fn log_summary(&self) {}
+}
+
+/// 使用哪种后端回答题目，集成的配置文件中使用和命令行相同的名字
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnswererKind {
+    /// OpenAI 兼容的 `/chat/completions` 接口
+    #[default]
+    #[value(name = "openai")]
+    #[serde(rename = "openai")]
+    OpenAi,
+    /// Anthropic 的 Messages API
+    Anthropic,
+    /// Gemini 的 `generateContent` 接口
+    Gemini,
+    /// 本地运行的 Ollama 的 `/api/chat` 接口，不计费
+    Ollama,
+    /// 本地运行的 llama.cpp server 的 `/v1/chat/completions` 接口，不计费
+    LlamaCpp,
+}
+
+impl AnswererKind {
+    /// 是否是本地运行的模型，本地模型不需要 API KEY
+    pub fn is_local(&self) -> bool {
+        matches!(self, AnswererKind::Ollama | AnswererKind::LlamaCpp)
+    }
+}
+
+pub fn from_args(ctx: &Context) -> Box<dyn Answerer> {
+    // 所有模型共用一个 vcr，重放时按同一份记录匹配，记录时串行写入
+    let vcr = Vcr::from_args(ctx).map(Arc::new);
+    if Cascade::enabled(ctx) {
+        return Box::new(Cascade::from_args(ctx, vcr));
+    }
+    base_from_args(ctx, vcr)
+}
+
+/// 不考虑重新回答时的后端
+fn base_from_args(ctx: &Context, vcr: Option<Arc<Vcr>>) -> Box<dyn Answerer> {
+    if ctx.ensemble.is_some() {
+        return Box::new(Ensemble::from_args(ctx, vcr));
+    }
+    backend_from_args(ctx, vcr)
+}
+
+/// 单个模型的后端
+fn backend_from_args(ctx: &Context, vcr: Option<Arc<Vcr>>) -> Box<dyn Answerer> {
+    log::debug!("answerer: {:?}", ctx.answerer);
+    let backend: Box<dyn Answerer> = match ctx.answerer {
+        AnswererKind::OpenAi => Box::new(Multimodal::from_args(ctx, vcr)),
+        AnswererKind::Anthropic => Box::new(Anthropic::from_args(ctx, vcr)),
+        AnswererKind::Gemini => Box::new(Gemini::from_args(ctx, vcr)),
+        AnswererKind::Ollama => Box::new(Local::from_args(ctx, LocalServer::Ollama, vcr)),
+        AnswererKind::LlamaCpp => Box::new(Local::from_args(ctx, LocalServer::LlamaCpp, vcr)),
+    };
+    if ctx.answer_verify {
+        return Box::new(Verify::new(ctx.api_model.clone(), backend));
+    }
+    backend
+}
+
+/// 模型接口的地址、模型、计费、超时以及重试
+#[derive(Clone, Debug)]
+pub struct Endpoint {
+    pub url: String,
+    pub model: String,
+    pub key: String,
+    pub cost: Cost,
+    pub timeout: Duration,
+    pub retry: Retry,
+    /// 发送之前图片的处理方式
+    pub image: ImageOptions,
+    /// 记录或者重放请求
+    pub vcr: Option<Arc<Vcr>>,
+}
+
+impl Endpoint {
+    pub fn from_args(ctx: &Context, vcr: Option<Arc<Vcr>>) -> Self {
+        Self {
+            url: ctx.api_url.clone(),
+            model: ctx.api_model.clone(),
+            key: ctx.api_key.clone(),
+            cost: Cost::from_args(ctx),
+            timeout: Duration::from_secs(ctx.api_timeout),
+            retry: Retry::new(Duration::from_secs(ctx.api_retry_budget)),
+            image: ImageOptions::from_args(ctx),
+            vcr,
+        }
+    }
+
+    /// 发送请求，开启记录或者重放时经过 `vcr`
+    fn send(&self, request: impl Fn() -> RequestBuilder) -> Option<Response> {
+        match &self.vcr {
+            Some(vcr) => vcr.send(&self.retry, request),
+            None => http::send(&self.retry, request),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn for_test(url: String) -> Self {
+        Self {
+            url,
+            model: "test-model".to_owned(),
+            key: "sk-test".to_owned(),
+            cost: Cost::default(),
+            timeout: Duration::from_secs(10),
+            retry: Retry {
+                base: Duration::from_millis(10),
+                max_delay: Duration::from_millis(100),
+                budget: Duration::from_secs(1),
+            },
+            image: ImageOptions::default(),
+            vcr: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub input_tokens: u64,
+    /// 包括思考的 token
+    pub output_tokens: u64,
+    /// 输出中用于思考的 token，接口没有返回时为 0
+    pub reasoning_tokens: u64,
+    pub cost: f64,
+}
+
+impl Usage {
+    pub fn tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+impl std::ops::Add for Usage {
+    type Output = Usage;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Usage {
+            input_tokens: self.input_tokens + rhs.input_tokens,
+            output_tokens: self.output_tokens + rhs.output_tokens,
+            reasoning_tokens: self.reasoning_tokens + rhs.reasoning_tokens,
+            cost: self.cost + rhs.cost,
+        }
+    }
+}
+
+/// 每百万 token 的费用
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cost {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl Cost {
+    pub fn from_args(ctx: &Context) -> Self {
+        Self {
+            input: ctx.api_cost_input,
+            output: ctx.api_cost_output,
+        }
+    }
+
+    pub fn usage(&self, input_tokens: u64, output_tokens: u64) -> Usage {
+        Usage {
+            input_tokens,
+            output_tokens,
+            reasoning_tokens: 0,
+            cost: input_tokens as f64 / 1_000_000f64 * self.input
+                + output_tokens as f64 / 1_000_000f64 * self.output,
+        }
+    }
+}
+
+/// 模型的回答
+#[derive(Clone, Debug)]
+pub struct Reply {
+    pub answer: Answer,
+    /// 模型自己给出的置信度，范围是 0 到 1，按文字解析时没有
+    pub confidence: Option<f32>,
+    /// 由 logprobs 换算的 A、B、C、D 的概率
+    pub distribution: Option<[f32; 4]>,
+    /// 模型转写的题目的文字
+    pub text: Option<QuestionText>,
+    /// 模型回答的原文，自我检查时作为第一轮的回答
+    pub message: Option<String>,
+}
+
+impl Reply {
+    /// 答案正确的概率，优先使用 logprobs，其次是模型给出的置信度
+    pub fn probability(&self) -> Option<f32> {
+        self.distribution
+            .map(|x| x[self.answer as usize])
+            .or(self.confidence)
+    }
+}
+
+impl From<Answer> for Reply {
+    fn from(answer: Answer) -> Self {
+        Self {
+            answer,
+            confidence: None,
+            distribution: None,
+            text: None,
+            message: None,
+        }
+    }
+}
+
+/// 从 OpenAI 格式的 `logprobs` 中找到第一个选项字母的 token，
+/// 将它的 `top_logprobs` 换算成 A、B、C、D 的概率
+fn distribution_from_logprobs(logprobs: &serde_json::Value) -> Option<[f32; 4]> {
+    let letter = |x: &serde_json::Value| {
+        let token = x["token"].as_str()?;
+        let token = token.trim().trim_matches('"').to_ascii_uppercase();
+        match token.as_str() {
+            "A" => Some(Answer::A),
+            "B" => Some(Answer::B),
+            "C" => Some(Answer::C),
+            "D" => Some(Answer::D),
+            _ => None,
+        }
+    };
+    let content = logprobs["content"].as_array()?;
+    let item = content.iter().find(|x| letter(x).is_some())?;
+    let mut distribution = [0f32; 4];
+    match item["top_logprobs"].as_array() {
+        Some(tops) if !tops.is_empty() => {
+            // 同一个字母可能有多个 token，例如 "A" 和 " A"
+            for top in tops {
+                if let (Some(answer), Some(logprob)) = (letter(top), top["logprob"].as_f64()) {
+                    distribution[answer as usize] += logprob.exp() as f32;
+                }
+            }
+        }
+        _ => distribution[letter(item)? as usize] = item["logprob"].as_f64()?.exp() as f32,
+    }
+    let sum: f32 = distribution.iter().sum();
+    if sum <= 0.0 {
+        return None;
+    }
+    distribution.iter_mut().for_each(|x| *x /= sum);
+    Some(distribution)
+}
+
+/// 结构化输出的 JSON Schema，需要转写时加上题干和选项的文字
+fn reply_schema(question: &Question) -> serde_json::Value {
+    let mut schema = json!({
+        "type": "object",
+        "properties": {
+            "answer": {
+                "type": "string",
+                "enum": ["A", "B", "C", "D"],
+            },
+            "confidence": {
+                "type": "number",
+                "minimum": 0,
+                "maximum": 1,
+            },
+        },
+        "required": ["answer", "confidence"],
+        "additionalProperties": false,
+    });
+    if question.transcribe {
+        schema["properties"]["question"] = json!({ "type": "string" });
+        schema["properties"]["options"] = json!({
+            "type": "array",
+            "items": { "type": "string" },
+        });
+        schema["required"] = json!(["question", "options", "answer", "confidence"]);
+    }
+    schema
+}
+
+/// OpenAI 兼容接口请求 logprobs 的参数
+fn request_logprobs(body: &mut serde_json::Value) {
+    body["logprobs"] = json!(true);
+    // 同一个字母可能有多个 token，多取几个
+    body["top_logprobs"] = json!(10);
+}
+
+/// 将 OpenAI 兼容接口返回的 logprobs 加入回答
+fn with_logprobs(reply: Option<Reply>, logprobs: Option<&serde_json::Value>) -> Option<Reply> {
+    let mut reply = reply?;
+    reply.distribution = logprobs.and_then(distribution_from_logprobs);
+    match reply.distribution {
+        Some(distribution) => log::debug!("distribution: {distribution:.3?}"),
+        None => log::debug!("no logprobs for the answer token"),
+    }
+    Some(reply)
+}
+
+/// OpenAI 兼容接口的 `response_format`
+fn response_format(question: &Question) -> serde_json::Value {
+    json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "reply",
+            "strict": true,
+            "schema": reply_schema(question),
+        },
+    })
+}
+
+/// 对话中的一轮
+enum Turn<'a> {
+    User {
+        images: Vec<&'a RgbImage>,
+        text: String,
+    },
+    Assistant(String),
+}
+
+/// 请求中的对话，少样本示例的问答在前，然后是要回答的题目，自我检查时最后是检查答案的一轮
+fn turns(question: &Question, structured: bool) -> Vec<Turn<'_>> {
+    let with_text = |text: &str| format!("{}\n\n{text}", question.prompt.user);
+    let mut turns = vec![];
+    for example in question.examples.iter() {
+        turns.push(match &example.content {
+            ExampleContent::Image(img) => Turn::User {
+                images: vec![img],
+                text: question.prompt.user.clone(),
+            },
+            ExampleContent::Text(text) => Turn::User {
+                images: vec![],
+                text: with_text(text),
+            },
+        });
+        let answer = example.answer.to_str();
+        turns.push(Turn::Assistant(if structured {
+            json!({ "answer": answer, "confidence": 1.0 }).to_string()
+        } else {
+            answer.to_owned()
+        }));
+    }
+    turns.push(Turn::User {
+        images: question.images.iter().collect(),
+        text: match &question.text {
+            Some(text) => with_text(&text.to_string()),
+            None => question.prompt.user.clone(),
+        },
+    });
+    if let Some(reply) = &question.follow_up {
+        turns.push(Turn::Assistant(reply.clone()));
+        turns.push(Turn::User {
+            images: vec![],
+            text: VERIFY_PROMPT.to_owned(),
+        });
+    }
+    turns
+}
+
+/// OpenAI 格式的 `messages`，有系统提示词时放在最前面
+fn openai_messages(
+    question: &Question,
+    structured: bool,
+    image: &ImageOptions,
+) -> Vec<serde_json::Value> {
+    let mut messages = vec![];
+    if let Some(system) = &question.prompt.system {
+        messages.push(json!({
+            "role": "system",
+            "content": system,
+        }));
+    }
+    for turn in turns(question, structured) {
+        messages.push(match turn {
+            Turn::User { images, text } => {
+                let mut content: Vec<_> = images
+                    .into_iter()
+                    .map(|img| {
+                        json!({
+                            "type": "image_url",
+                            "image_url": {
+                                "url": image.encode(img).data_url(),
+                            },
+                        })
+                    })
+                    .collect();
+                content.push(json!({
+                    "type": "text",
+                    "text": text,
+                }));
+                json!({
+                    "role": "user",
+                    "content": content,
+                })
+            }
+            Turn::Assistant(text) => json!({
+                "role": "assistant",
+                "content": text,
+            }),
+        });
+    }
+    messages
+}
+
+fn new_headers(headers: &[(&str, &str)]) -> HeaderMap {
+    let mut req_headers = HeaderMap::new();
+    for (key, value) in headers {
+        let name = HeaderName::from_bytes(key.as_bytes()).unwrap();
+        let value = HeaderValue::from_str(value).unwrap();
+        req_headers.insert(name, value);
+    }
+    req_headers
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Answer {
+    A = 0,
+    B = 1,
+    C = 2,
+    D = 3,
+}
+
+impl Answer {
+    pub fn random() -> Self {
+        Self::from_index(rand::rng().random_range(0..4))
+    }
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            0 => Answer::A,
+            1 => Answer::B,
+            2 => Answer::C,
+            3 => Answer::D,
+            _ => panic!("invalid answer index: {index}"),
+        }
+    }
+    pub fn to_str<'a>(self) -> &'a str {
+        match self {
+            Answer::A => "A",
+            Answer::B => "B",
+            Answer::C => "C",
+            Answer::D => "D",
+        }
+    }
+}
+
+/// 无法从响应中取得回答的原因
+#[derive(Debug)]
+enum ResponseError {
+    /// 无法读取响应或者不是预期的格式
+    Invalid(String),
+    /// 没有回答，例如被截断或者被内容审核拦截
+    Empty(String),
+    /// 模型拒绝回答
+    Refusal(String),
+}
+
+impl std::fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(e) => write!(f, "invalid response: {e}"),
+            Self::Empty(reason) => write!(f, "no answer in the response: {reason}"),
+            Self::Refusal(refusal) => write!(f, "refused to answer: {refusal}"),
+        }
+    }
+}
+
+/// 读取响应并解析为 `T`
+fn parse_body<T: serde::de::DeserializeOwned>(
+    resp: reqwest::blocking::Response,
+) -> Result<T, ResponseError> {
+    let text = resp
+        .text()
+        .map_err(|e| ResponseError::Invalid(e.to_string()))?;
+    log::trace!("{text}");
+    serde_json::from_str(&text).map_err(|e| ResponseError::Invalid(format!("{e}: {text}")))
+}
+
+/// 先按 JSON 解析，失败时按文字解析
+fn parse_reply(message: &str) -> Option<Reply> {
+    let json = message
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let value = serde_json::from_str::<serde_json::Value>(json).ok();
+    let reply = match value.as_ref().and_then(reply_from_json) {
+        Some(reply) => reply,
+        None => Reply::from(parse_answer(message)?),
+    };
+    Some(Reply {
+        message: Some(message.to_owned()),
+        ..reply
+    })
+}
+
+/// 从 `{"answer": "A", "confidence": 0.9}` 中取出回答
+fn reply_from_json(value: &serde_json::Value) -> Option<Reply> {
+    let answer = value.get("answer")?.as_str()?;
+    let answer = match answer.trim().to_ascii_uppercase().as_str() {
+        "A" => Answer::A,
+        "B" => Answer::B,
+        "C" => Answer::C,
+        "D" => Answer::D,
+        _ => {
+            log::warn!("Unknown answer: {answer}");
+            return None;
+        }
+    };
+    let confidence = value
+        .get("confidence")
+        .and_then(|x| x.as_f64())
+        .map(|x| x.clamp(0.0, 1.0) as f32);
+    let text = value["question"].as_str().map(|stem| QuestionText {
+        stem: stem.to_owned(),
+        options: value["options"]
+            .as_array()
+            .map(|x| {
+                x.iter()
+                    .filter_map(|x| x.as_str())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    });
+    Some(Reply {
+        answer,
+        confidence,
+        distribution: None,
+        text,
+        message: None,
+    })
+}
+
+fn parse_answer(arg_answer: &str) -> Option<Answer> {
+    let answer = arg_answer.trim();
+    let answer = if answer.len() == 1 {
+        answer.chars().next().unwrap()
+    } else {
+        // 先分析再回答时以最后出现的答案为准
+        let pos = answer.rfind("答案")?;
+        let mut ans = ' ';
+        for c in answer[pos..].chars() {
+            if c.is_ascii_alphabetic() {
+                ans = c;
+                break;
+            }
+        }
+        ans
+    };
+
+    match answer.to_ascii_uppercase() {
+        'A' => Some(Answer::A),
+        'B' => Some(Answer::B),
+        'C' => Some(Answer::C),
+        'D' => Some(Answer::D),
+        _ => {
+            log::warn!("Unknown answer: {arg_answer}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reply() {
+        let reply = parse_reply(r#"{"answer": "C", "confidence": 0.82}"#).unwrap();
+        assert!(matches!(reply.answer, Answer::C));
+        assert_eq!(reply.confidence, Some(0.82));
+
+        let reply = parse_reply("```json\n{\"answer\": \"b\", \"confidence\": 1.5}\n```").unwrap();
+        assert!(matches!(reply.answer, Answer::B));
+        assert_eq!(reply.confidence, Some(1.0));
+
+        let reply = parse_reply("答案：D").unwrap();
+        assert!(matches!(reply.answer, Answer::D));
+        assert_eq!(reply.confidence, None);
+
+        assert!(parse_reply(r#"{"answer": "E"}"#).is_none());
+
+        let reply = parse_reply(
+            r#"{"question": "1 + 1 = ?", "options": ["1", "2", "3", "4"], "answer": "B", "confidence": 0.99}"#,
+        )
+        .unwrap();
+        let text = reply.text.unwrap();
+        assert_eq!(text.stem, "1 + 1 = ?");
+        assert_eq!(text.options, ["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_distribution_from_logprobs() {
+        let logprobs = json!({
+            "content": [
+                {"token": "{\"", "logprob": 0.0, "top_logprobs": []},
+                {"token": "answer", "logprob": 0.0, "top_logprobs": []},
+                {
+                    "token": "\"B",
+                    "logprob": -0.2,
+                    "top_logprobs": [
+                        {"token": "\"B", "logprob": -0.2},
+                        {"token": "\"A", "logprob": -2.0},
+                        {"token": " B", "logprob": -3.0},
+                        {"token": "\"", "logprob": -4.0},
+                    ],
+                },
+            ],
+        });
+        let distribution = distribution_from_logprobs(&logprobs).unwrap();
+        let b = (-0.2f32).exp() + (-3.0f32).exp();
+        let a = (-2.0f32).exp();
+        assert!((distribution[1] - b / (a + b)).abs() < 1e-6);
+        assert!((distribution[0] - a / (a + b)).abs() < 1e-6);
+        assert_eq!((distribution[2], distribution[3]), (0.0, 0.0));
+
+        let reply = Reply {
+            distribution: Some(distribution),
+            confidence: Some(0.99),
+            ..Reply::from(Answer::B)
+        };
+        assert_eq!(reply.probability(), Some(distribution[1]));
+
+        assert!(distribution_from_logprobs(&json!({"content": []})).is_none());
+    }
+
+    #[test]
+    fn test_openai_messages() {
+        use crate::{few_shot::Example, prompt::Template};
+
+        let mut question = Question {
+            examples: vec![
+                Example {
+                    name: "text".to_owned(),
+                    content: ExampleContent::Text("1 + 2 = ?\nA. 1\nB. 2\nC. 3\nD. 4".to_owned()),
+                    answer: Answer::C,
+                },
+                Example {
+                    name: "image".to_owned(),
+                    content: ExampleContent::Image(RgbImage::new(8, 8)),
+                    answer: Answer::A,
+                },
+            ]
+            .into(),
+            ..Question::for_test()
+        };
+        question.prompt = Template::builtin(String::new()).render(&question, true);
+        question.prompt.system = Some("你是一个答题助手。".to_owned());
+        let messages = openai_messages(&question, true, &ImageOptions::default());
+        let roles: Vec<_> = messages
+            .iter()
+            .map(|x| x["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            roles,
+            ["system", "user", "assistant", "user", "assistant", "user"]
+        );
+        // 文字示例没有图片，题目的文字在提示词之后
+        let content = messages[1]["content"].as_array().unwrap();
+        assert_eq!(content.len(), 1);
+        assert!(content[0]["text"].as_str().unwrap().ends_with("D. 4"));
+        let reply = parse_reply(messages[2]["content"].as_str().unwrap()).unwrap();
+        assert!(matches!(reply.answer, Answer::C));
+        assert_eq!(messages[3]["content"].as_array().unwrap().len(), 2);
+        assert_eq!(messages[5]["content"][0]["type"], "image_url");
+
+        // 非结构化输出时示例的回答只有选项字母
+        assert_eq!(
+            openai_messages(&question, false, &ImageOptions::default())[4]["content"],
+            "A"
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use std::{io::BufReader, sync::Arc};
+
+use reqwest::blocking::{Client, Response};
+use serde_json::json;
+
+use crate::{compose::Question, context::Context};
+
+use super::{
+    Answer, Answerer, Endpoint, Reply, Usage, Vcr,
+    completion::{ChatCompletion, Tokens},
+    new_headers, openai_messages, parse_body, parse_reply, request_logprobs, response_format,
+    stream::{Collected, Drains, Events, early_answer},
+    with_logprobs,
+};
+
+/// OpenAI 兼容接口中控制思考的参数，不同厂商使用的参数不同
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReasoningStyle {
+    /// `"thinking": {"type": "enabled" | "disabled"}`，例如豆包、GLM
+    #[default]
+    Thinking,
+    /// 开启思考时发送 `reasoning_effort`，例如 OpenAI 的推理模型
+    Effort,
+    /// `enable_thinking`，开启时还发送 `thinking_budget`，例如通义千问
+    EnableThinking,
+    /// 不发送思考相关的参数
+    None,
+}
+
+/// OpenAI 的 `reasoning_effort`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReasoningEffort {
+    Minimal,
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    fn to_str(self) -> &'static str {
+        match self {
+            Self::Minimal => "minimal",
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+}
+
+/// 思考的设置
+#[derive(Clone, Copy, Debug)]
+pub struct Reasoning {
+    pub style: ReasoningStyle,
+    pub thinking: bool,
+    pub effort: ReasoningEffort,
+    pub budget: u32,
+}
+
+impl Reasoning {
+    pub fn from_args(ctx: &Context) -> Self {
+        Self {
+            style: ctx.answer_reasoning,
+            thinking: ctx.answer_thinking,
+            effort: ctx.answer_reasoning_effort,
+            budget: ctx.answer_thinking_budget,
+        }
+    }
+
+    /// 按厂商的格式在请求中加入思考的参数
+    fn apply(&self, body: &mut serde_json::Value) {
+        match self.style {
+            ReasoningStyle::Thinking => {
+                body["thinking"] = json!({
+                    "type": if self.thinking { "enabled" } else { "disabled" },
+                });
+            }
+            // 不支持推理的模型收到 reasoning_effort 会报错，只在开启时发送
+            ReasoningStyle::Effort if self.thinking => {
+                body["reasoning_effort"] = json!(self.effort.to_str());
+            }
+            ReasoningStyle::Effort => {}
+            ReasoningStyle::EnableThinking => {
+                body["enable_thinking"] = json!(self.thinking);
+                if self.thinking {
+                    body["thinking_budget"] = json!(self.budget);
+                }
+            }
+            ReasoningStyle::None => {}
+        }
+    }
+}
+
+/// OpenAI 兼容的 `/chat/completions` 接口
+pub struct Multimodal {
+    endpoint: Endpoint,
+    client: Client,
+    reasoning: Reasoning,
+    structured: bool,
+    logprobs: bool,
+    /// 使用流式回答，回答以选项字母开头时不等待回答结束
+    stream: bool,
+
+    tokens: Tokens,
+    drains: Drains,
+}
+
+impl Multimodal {
+    pub fn from_args(ctx: &Context, vcr: Option<Arc<Vcr>>) -> Self {
+        Self {
+            stream: ctx.answer_stream,
+            ..Self::new(
+                Endpoint::from_args(ctx, vcr),
+                Reasoning::from_args(ctx),
+                ctx.answer_structured,
+                ctx.answer_logprobs,
+            )
+        }
+    }
+    pub fn new(endpoint: Endpoint, reasoning: Reasoning, structured: bool, logprobs: bool) -> Self {
+        let client = Client::new();
+        Self {
+            endpoint,
+            client,
+            reasoning,
+            structured,
+            logprobs,
+            stream: false,
+            tokens: Tokens::default(),
+            drains: Drains::default(),
+        }
+    }
+
+    fn post(&self, question: &Question) -> Option<Response> {
+        let headers = new_headers(&[
+            ("Content-Type", "application/json"),
+            ("Authorization", &format!("Bearer {}", self.endpoint.key)),
+        ]);
+        let mut body = json!({
+            "model": self.endpoint.model,
+            "messages": openai_messages(question, self.structured, &self.endpoint.image),
+        });
+        self.reasoning.apply(&mut body);
+        if self.structured {
+            body["response_format"] = response_format(question);
+        }
+        if self.logprobs {
+            request_logprobs(&mut body);
+        }
+        if self.stream {
+            body["stream"] = json!(true);
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        let body = body.to_string();
+        log::debug!("request {}", self.endpoint.url);
+        let resp = self.endpoint.send(|| {
+            self.client
+                .post(&self.endpoint.url)
+                .headers(headers.clone())
+                .body(body.clone())
+                .timeout(self.endpoint.timeout)
+        })?;
+        Some(resp)
+    }
+
+    /// 逐块读取回答，可以提前确定答案时不再等待，返回收到的回答、提前确定的答案以及用量
+    fn complete_stream(
+        &self,
+        resp: Response,
+        question: &Question,
+    ) -> (Collected, Option<Answer>, Tokens) {
+        // 结构化输出的置信度和自我检查的分析在答案之后，需要完整的回答
+        let early = !self.structured && !question.verify;
+        let mut events = Events::new(BufReader::new(resp));
+        let mut collected = Collected::default();
+        let mut answer = None;
+        for chunk in &mut events {
+            log::trace!("{chunk:?}");
+            collected.push(&chunk);
+            answer = early.then(|| early_answer(&collected.content)).flatten();
+            if answer.is_some() {
+                break;
+            }
+        }
+        let tokens = match (answer, collected.tokens) {
+            // 每个数据块都有累计的用量时直接断开，停止生成剩余的回答
+            (Some(_), Some(tokens)) => {
+                log::debug!("early answer, cancel the rest of the stream");
+                tokens
+            }
+            (Some(_), None) => {
+                log::debug!("early answer, read the rest of the stream in the background");
+                self.drains.spawn(events);
+                Tokens::default()
+            }
+            (None, tokens) => tokens.unwrap_or_else(|| {
+                log::warn!("No usage in the stream");
+                Tokens::default()
+            }),
+        };
+        (collected, answer, tokens)
+    }
+}
+
+impl Answerer for Multimodal {
+    fn answer(&mut self, question: &Question) -> Option<Reply> {
+        log::debug!(
+            "question: layout: {:?}, labeled: {}, images: {}, prompt: {}",
+            question.layout,
+            question.labeled,
+            question.images.len(),
+            question.prompt.version
+        );
+        let resp = self.post(question)?;
+        let (result, early, tokens) = if self.stream {
+            let (collected, early, tokens) = self.complete_stream(resp, question);
+            let logprobs = collected.logprobs();
+            (Ok((collected.content, Some(logprobs))), early, tokens)
+        } else {
+            let completion: ChatCompletion =
+                parse_body(resp).inspect_err(|e| log::warn!("{e}")).ok()?;
+            let tokens = completion.tokens().unwrap_or_else(|| {
+                log::warn!("No usage in the response");
+                Tokens::default()
+            });
+            let result = completion
+                .reply()
+                .map(|(message, logprobs)| (message, logprobs.cloned()));
+            (result, None, tokens)
+        };
+        log::debug!(
+            "tokens: prompt: {}, completion: {}, reasoning: {}, total: {}",
+            tokens.prompt,
+            tokens.completion,
+            tokens.reasoning,
+            tokens.prompt + tokens.completion
+        );
+        self.tokens = self.tokens + tokens;
+        log::trace!(
+            "acc tokens: prompt: {}, completion: {}, total: {}",
+            self.tokens.prompt,
+            self.tokens.completion,
+            self.tokens.prompt + self.tokens.completion
+        );
+
+        // 拒绝回答或者回答为空时也已经产生了用量
+        let (message, logprobs) = result.inspect_err(|e| log::warn!("{e}")).ok()?;
+        log::debug!("answer: {message:?}");
+        log::debug!("raw answer: {}", message);
+        let reply = match early {
+            Some(answer) => Some(Reply {
+                message: Some(message),
+                ..Reply::from(answer)
+            }),
+            None => parse_reply(&message),
+        };
+        if self.logprobs {
+            return with_logprobs(reply, logprobs.as_ref());
+        }
+        reply
+    }
+
+    fn usage(&self) -> Usage {
+        let tokens = self.tokens + self.drains.tokens();
+        Usage {
+            reasoning_tokens: tokens.reasoning,
+            ..self.endpoint.cost.usage(tokens.prompt, tokens.completion)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        answerer::{Answer, Vcr, mock::MockServer},
+        logging,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_answer_logprobs() {
+        logging::init_for_test();
+        let resp = json!({
+            "choices": [
+                {
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {"role": "assistant", "content": "A"},
+                    "logprobs": {
+                        "content": [
+                            {
+                                "token": "A",
+                                "logprob": -0.4,
+                                "top_logprobs": [
+                                    {"token": "A", "logprob": -0.4},
+                                    {"token": "C", "logprob": -1.2},
+                                ],
+                            },
+                        ],
+                    },
+                },
+            ],
+            "usage": {
+                "prompt_tokens": 900,
+                "completion_tokens": 201,
+                "completion_tokens_details": {"reasoning_tokens": 200},
+            },
+        });
+        let server = MockServer::start(vec![(200, resp.to_string())]);
+        let endpoint = Endpoint::for_test(server.url("/v1/chat/completions"));
+        let reasoning = Reasoning {
+            style: ReasoningStyle::Effort,
+            thinking: true,
+            effort: ReasoningEffort::Low,
+            budget: 2048,
+        };
+        let mut answerer = Multimodal::new(endpoint, reasoning, false, true);
+        let question = Question::for_test();
+
+        let reply = answerer.answer(&question).unwrap();
+        assert!(matches!(reply.answer, Answer::A));
+        let (a, c) = ((-0.4f32).exp(), (-1.2f32).exp());
+        assert!((reply.probability().unwrap() - a / (a + c)).abs() < 1e-6);
+
+        let body = server.requests()[0].json();
+        assert_eq!(body["logprobs"], true);
+        assert_eq!(body["top_logprobs"], 10);
+        assert_eq!(body["reasoning_effort"], "low");
+        assert!(body.get("thinking").is_none());
+        let usage = answerer.usage();
+        assert_eq!((usage.output_tokens, usage.reasoning_tokens), (201, 200));
+        assert_eq!(
+            server.requests()[0].header("Authorization"),
+            Some("Bearer sk-test")
+        );
+    }
+
+    #[test]
+    fn test_reasoning() {
+        let body = |style, thinking| {
+            let mut body = json!({});
+            let reasoning = Reasoning {
+                style,
+                thinking,
+                effort: ReasoningEffort::High,
+                budget: 4096,
+            };
+            reasoning.apply(&mut body);
+            body
+        };
+        assert_eq!(
+            body(ReasoningStyle::Thinking, false),
+            json!({"thinking": {"type": "disabled"}})
+        );
+        assert_eq!(
+            body(ReasoningStyle::Effort, true),
+            json!({"reasoning_effort": "high"})
+        );
+        assert_eq!(body(ReasoningStyle::Effort, false), json!({}));
+        assert_eq!(
+            body(ReasoningStyle::EnableThinking, true),
+            json!({"enable_thinking": true, "thinking_budget": 4096})
+        );
+        assert_eq!(
+            body(ReasoningStyle::EnableThinking, false),
+            json!({"enable_thinking": false})
+        );
+        assert_eq!(body(ReasoningStyle::None, true), json!({}));
+    }
+
+    #[test]
+    fn test_answer_stream() {
+        logging::init_for_test();
+        let chunk = |content: &str| {
+            let chunk = json!({"choices": [{"index": 0, "delta": {"content": content}}]});
+            format!("data: {chunk}\n\n")
+        };
+        let usage = json!({
+            "choices": [],
+            "usage": {"prompt_tokens": 900, "completion_tokens": 40},
+        });
+        let sse = [
+            chunk("B"),
+            chunk("。因为"),
+            chunk("……"),
+            format!("data: {usage}\n\n"),
+        ]
+        .concat();
+        let server = MockServer::start(vec![(200, format!("{sse}data: [DONE]\n\n"))]);
+        let endpoint = Endpoint::for_test(server.url("/v1/chat/completions"));
+        let reasoning = Reasoning {
+            style: ReasoningStyle::None,
+            thinking: false,
+            effort: ReasoningEffort::Medium,
+            budget: 2048,
+        };
+        let mut answerer = Multimodal::new(endpoint, reasoning, false, false);
+        answerer.stream = true;
+        let question = Question::for_test();
+
+        let reply = answerer.answer(&question).unwrap();
+        assert!(matches!(reply.answer, Answer::B));
+        assert_eq!(reply.message.as_deref(), Some("B。因为"));
+        // 剩余的回答在后台读取，用量来自最后一个数据块
+        let usage = answerer.usage();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (900, 40));
+
+        let body = server.requests()[0].json();
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["stream_options"]["include_usage"], true);
+    }
+
+    #[test]
+    fn test_replay() {
+        logging::init_for_test();
+        let path = "tests/fixtures/cassettes/multimodal.jsonl";
+        let endpoint = Endpoint {
+            vcr: Some(std::sync::Arc::new(Vcr::replay(path.as_ref()))),
+            ..Endpoint::for_test("http://127.0.0.1:1/v1/chat/completions".to_owned())
+        };
+        let reasoning = Reasoning {
+            style: ReasoningStyle::Thinking,
+            thinking: false,
+            effort: ReasoningEffort::Medium,
+            budget: 2048,
+        };
+        let mut answerer = Multimodal::new(endpoint, reasoning, false, false);
+        let question = Question::for_test();
+
+        let reply = answerer.answer(&question).unwrap();
+        assert!(matches!(reply.answer, Answer::B));
+        // 同一道题的第二次请求重放第二条记录
+        answerer.stream = true;
+        let reply = answerer.answer(&question).unwrap();
+        assert!(matches!(reply.answer, Answer::C));
+        assert!(answerer.answer(&question).is_none());
+
+        let usage = answerer.usage();
+        assert_eq!(
+            (
+                usage.input_tokens,
+                usage.output_tokens,
+                usage.reasoning_tokens
+            ),
+            (812 * 2, 153 + 2, 140)
+        );
+    }
+}
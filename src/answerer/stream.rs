@@ -0,0 +1,201 @@
+//! OpenAI 兼容接口的流式回答，按 SSE 格式逐块读取
+
+use std::{io::BufRead, sync::Mutex, thread::JoinHandle};
+
+use serde_json::json;
+
+use super::{
+    Answer,
+    completion::{ChatCompletion, Tokens},
+};
+
+/// SSE 中的每个 `data:` 数据块，读到 `[DONE]` 或者连接断开时结束
+pub(super) struct Events<R> {
+    lines: std::io::Lines<R>,
+}
+
+impl<R: BufRead> Events<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Events<R> {
+    type Item = ChatCompletion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in &mut self.lines {
+            let line = line
+                .inspect_err(|e| log::warn!("Failed to read the stream: {e}"))
+                .ok()?;
+            // 忽略空行、注释以及 event: 和 id: 等字段
+            let Some(data) = line.strip_prefix("data:") else {
+                continue;
+            };
+            let data = data.trim();
+            if data == "[DONE]" {
+                return None;
+            }
+            match serde_json::from_str(data) {
+                Ok(chunk) => return Some(chunk),
+                Err(e) => log::warn!("Invalid stream chunk: {e}: {data}"),
+            }
+        }
+        None
+    }
+}
+
+/// 已经收到的回答
+#[derive(Debug, Default)]
+pub(super) struct Collected {
+    pub content: String,
+    /// logprobs 中的每个 token
+    pub logprobs: Vec<serde_json::Value>,
+    /// 有的接口在每个数据块中都返回累计的用量，有的只在最后一个数据块中返回
+    pub tokens: Option<Tokens>,
+}
+
+impl Collected {
+    pub fn push(&mut self, chunk: &ChatCompletion) {
+        if let Some(tokens) = chunk.tokens() {
+            self.tokens = Some(tokens);
+        }
+        if let Some(text) = chunk.delta() {
+            self.content.push_str(&text);
+        }
+        // 包含 usage 的最后一个数据块的 choices 是空的
+        let items = chunk
+            .choices
+            .first()
+            .and_then(|x| x.logprobs.as_ref()?["content"].as_array());
+        if let Some(items) = items {
+            self.logprobs.extend(items.iter().cloned());
+        }
+    }
+
+    /// 与非流式回答格式相同的 logprobs
+    pub fn logprobs(&self) -> serde_json::Value {
+        json!({ "content": self.logprobs })
+    }
+}
+
+/// 可以确定答案时返回该字母，只接受两种格式：
+///
+/// - 第一行只有一个选项字母，例如 `B\n` 或者 `B。因为……`
+/// - 明确的 `答案：B`，与 `parse_answer` 一样只看最后出现的“答案”
+///
+/// `A选项错误，正确答案是B` 之类以字母开头的解释不会被当作答案
+pub(super) fn early_answer(content: &str) -> Option<Answer> {
+    let letter = |c| match c {
+        'A' => Some(Answer::A),
+        'B' => Some(Answer::B),
+        'C' => Some(Answer::C),
+        'D' => Some(Answer::D),
+        _ => None,
+    };
+    let mut chars = content.trim_start().chars();
+    let first = chars.next().and_then(letter);
+    // 只有一个字母时还不能确定，后面可能还有其他文字
+    if let (Some(answer), Some('\n' | '\r' | '。')) = (first, chars.next()) {
+        return Some(answer);
+    }
+    let rest = &content[content.rfind("答案")? + "答案".len()..];
+    let mut chars = rest.trim_start_matches([':', '：', ' ']).chars();
+    let answer = chars.next().and_then(letter)?;
+    let next = chars.next()?;
+    (!next.is_ascii_alphanumeric()).then_some(answer)
+}
+
+/// 提前得到答案后在后台读取剩余的流，只为了最后一个数据块中的用量
+#[derive(Default)]
+pub(super) struct Drains(Mutex<DrainState>);
+
+#[derive(Default)]
+struct DrainState {
+    pending: Vec<JoinHandle<Option<Tokens>>>,
+    tokens: Tokens,
+}
+
+impl Drains {
+    pub fn spawn<R: BufRead + Send + 'static>(&self, events: Events<R>) {
+        let handle = std::thread::spawn(move || events.filter_map(|chunk| chunk.tokens()).last());
+        self.0.lock().unwrap().pending.push(handle);
+    }
+
+    /// 等待后台的读取结束，返回这些请求累计的用量
+    pub fn tokens(&self) -> Tokens {
+        let mut state = self.0.lock().unwrap();
+        for handle in std::mem::take(&mut state.pending) {
+            match handle.join().unwrap() {
+                Some(tokens) => state.tokens = state.tokens + tokens,
+                None => log::warn!("No usage in the rest of the stream"),
+            }
+        }
+        state.tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::answerer::parse_answer;
+
+    use super::*;
+
+    #[test]
+    fn test_events() {
+        let sse = ": keep-alive\n\n\
+            data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"想一想\"}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"C\"}}]}\n\n\
+            data: not json\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"\\n因为\"}}]}\n\n\
+            data: {\"choices\":[],\"usage\":{\"prompt_tokens\":900,\"completion_tokens\":30,\"completion_tokens_details\":{\"reasoning_tokens\":25}}}\n\n\
+            data: [DONE]\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"after done\"}}]}\n\n";
+        let mut collected = Collected::default();
+        let mut early = None;
+        for chunk in Events::new(Cursor::new(sse)) {
+            collected.push(&chunk);
+            early = early.or(early_answer(&collected.content));
+        }
+        assert_eq!(collected.content, "C\n因为");
+        assert_eq!(early, Some(Answer::C));
+        assert_eq!(
+            collected.tokens,
+            Some(Tokens {
+                prompt: 900,
+                completion: 30,
+                reasoning: 25,
+            })
+        );
+    }
+
+    #[test]
+    fn test_early_answer() {
+        assert_eq!(early_answer("B"), None);
+        assert_eq!(early_answer(" B\n"), Some(Answer::B));
+        assert_eq!(early_answer("D。因为"), Some(Answer::D));
+        assert_eq!(early_answer("B选项"), None);
+        assert_eq!(early_answer("A选项错误，正确答案是B"), None);
+        assert_eq!(early_answer("C，因为"), None);
+        assert_eq!(early_answer("分析图片……\n答案：C\n"), Some(Answer::C));
+        assert_eq!(early_answer("答案: A。"), Some(Answer::A));
+        assert_eq!(early_answer("答案：B"), None);
+        assert_eq!(early_answer("Answer: B"), None);
+        assert_eq!(early_answer("{\"answer\""), None);
+        assert_eq!(early_answer(""), None);
+
+        // 边接收边判断，前面不确定的“答案”不能提前确定
+        let content = "答案可能是A，再看一下选项……答案：C\n";
+        let early = content
+            .char_indices()
+            .find_map(|(i, _)| early_answer(&content[..i]))
+            .or(early_answer(content));
+        assert_eq!(early, Some(Answer::C));
+        assert_eq!(parse_answer(content), Some(Answer::C));
+    }
+}
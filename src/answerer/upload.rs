@@ -0,0 +1,240 @@
+//! 发送给模型之前处理和编码图片，图片的大小决定了大部分输入 token
+
+use std::io::Cursor;
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use image::{
+    DynamicImage, ImageFormat, RgbImage,
+    codecs::jpeg::JpegEncoder,
+    imageops::{self, FilterType},
+};
+
+use crate::context::Context;
+
+/// 图片的编码格式
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UploadFormat {
+    /// 有损压缩，体积小，质量由 --image-quality 决定
+    #[default]
+    Jpeg,
+    /// 无损压缩，文字边缘清晰，但是体积大
+    Png,
+    /// 无损压缩，通常比 PNG 小，`image` 只支持无损的 WebP 编码
+    Webp,
+}
+
+/// 图片的处理方式
+#[derive(Clone, Copy, Debug)]
+pub struct ImageOptions {
+    /// 长边超过时等比例缩小
+    pub max_edge: Option<u32>,
+    pub grayscale: bool,
+    /// 对比度调整的百分比，0 为不调整
+    pub contrast: f32,
+    pub format: UploadFormat,
+    /// JPEG 的质量，1 到 100，其他格式是无损的，不使用
+    pub quality: u8,
+}
+
+impl Default for ImageOptions {
+    /// 不做处理，按 `image` 默认的质量编码为 JPEG
+    fn default() -> Self {
+        Self {
+            max_edge: None,
+            grayscale: false,
+            contrast: 0.0,
+            format: UploadFormat::Jpeg,
+            quality: 75,
+        }
+    }
+}
+
+/// base64 编码的图片
+pub struct EncodedImage {
+    pub mime: &'static str,
+    pub data: String,
+}
+
+impl EncodedImage {
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime, self.data)
+    }
+}
+
+impl ImageOptions {
+    pub fn from_args(ctx: &Context) -> Self {
+        Self {
+            max_edge: ctx.image_max_edge,
+            grayscale: ctx.image_grayscale,
+            contrast: ctx.image_contrast,
+            format: ctx.image_format,
+            quality: ctx.image_quality,
+        }
+    }
+
+    /// 缩小、调整对比度以及转为灰度
+    fn process(&self, img: &RgbImage) -> DynamicImage {
+        let mut img = match self.max_edge {
+            Some(max_edge) if img.width().max(img.height()) > max_edge => {
+                let scale = max_edge as f32 / img.width().max(img.height()) as f32;
+                let width = ((img.width() as f32 * scale).round() as u32).max(1);
+                let height = ((img.height() as f32 * scale).round() as u32).max(1);
+                imageops::resize(img, width, height, FilterType::Lanczos3)
+            }
+            _ => img.clone(),
+        };
+        if self.contrast != 0.0 {
+            imageops::colorops::contrast_in_place(&mut img, self.contrast);
+        }
+        if self.grayscale {
+            DynamicImage::ImageLuma8(imageops::grayscale(&img))
+        } else {
+            DynamicImage::ImageRgb8(img)
+        }
+    }
+
+    pub fn encode(&self, img: &RgbImage) -> EncodedImage {
+        let img = self.process(img);
+        let mut buf = Vec::new();
+        let mime = match self.format {
+            UploadFormat::Jpeg => {
+                let encoder = JpegEncoder::new_with_quality(&mut buf, self.quality);
+                img.write_with_encoder(encoder).unwrap();
+                "image/jpeg"
+            }
+            UploadFormat::Png => {
+                img.write_to(&mut Cursor::new(&mut buf), ImageFormat::Png)
+                    .unwrap();
+                "image/png"
+            }
+            UploadFormat::Webp => {
+                img.write_to(&mut Cursor::new(&mut buf), ImageFormat::WebP)
+                    .unwrap();
+                "image/webp"
+            }
+        };
+        let (width, height) = (img.width(), img.height());
+        log::debug!(
+            "image: {width}x{height}, {} bytes, estimated tokens: openai: {}, anthropic: {}, gemini: {}, qwen: {}",
+            buf.len(),
+            estimate_tokens(Provider::OpenAi, width, height),
+            estimate_tokens(Provider::Anthropic, width, height),
+            estimate_tokens(Provider::Gemini, width, height),
+            estimate_tokens(Provider::Qwen, width, height),
+        );
+        EncodedImage {
+            mime,
+            data: BASE64_STANDARD.encode(&buf),
+        }
+    }
+}
+
+/// 计算图片 token 的方式，按各家公开的文档估计
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    /// GPT-4o 等模型的 high detail，512 像素的块每块 170，另加 85
+    OpenAi,
+    /// Claude，长边先缩小到 1568，每 750 个像素一个 token
+    Anthropic,
+    /// Gemini，两边都不超过 384 时为 258，否则按 768 像素的块每块 258
+    Gemini,
+    /// 通义千问、豆包等 ViT 模型，每 28x28 个像素一个 token，另加开始和结束标记
+    Qwen,
+}
+
+/// 估计一张图片的输入 token 数
+pub fn estimate_tokens(provider: Provider, width: u32, height: u32) -> u64 {
+    let (w, h) = (width.max(1) as f64, height.max(1) as f64);
+    match provider {
+        Provider::OpenAi => {
+            let scale = (2048.0 / w.max(h)).min(1.0);
+            let (w, h) = (w * scale, h * scale);
+            let scale = (768.0 / w.min(h)).min(1.0);
+            let (w, h) = (w * scale, h * scale);
+            let tiles = (w / 512.0).ceil() * (h / 512.0).ceil();
+            170 * tiles as u64 + 85
+        }
+        Provider::Anthropic => {
+            let scale = (1568.0 / w.max(h)).min(1.0);
+            (w * scale * h * scale / 750.0).ceil() as u64
+        }
+        Provider::Gemini => {
+            if w <= 384.0 && h <= 384.0 {
+                return 258;
+            }
+            let tiles = (w / 768.0).ceil() * (h / 768.0).ceil();
+            258 * tiles as u64
+        }
+        Provider::Qwen => {
+            let patches = |x: f64| (x / 28.0).round().max(1.0);
+            (patches(w) * patches(h)) as u64 + 2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+
+    fn decode(encoded: &EncodedImage) -> DynamicImage {
+        image::load_from_memory(&BASE64_STANDARD.decode(&encoded.data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_encode() {
+        let img = RgbImage::from_fn(400, 100, |x, _| Rgb([(x % 256) as u8, 40, 200]));
+
+        let encoded = ImageOptions::default().encode(&img);
+        assert_eq!(encoded.mime, "image/jpeg");
+        assert!(
+            encoded
+                .data_url()
+                .starts_with("data:image/jpeg;base64,/9j/")
+        );
+        assert_eq!(decode(&encoded).width(), 400);
+
+        let options = ImageOptions {
+            max_edge: Some(200),
+            grayscale: true,
+            contrast: 30.0,
+            quality: 50,
+            ..ImageOptions::default()
+        };
+        let decoded = decode(&options.encode(&img));
+        assert_eq!((decoded.width(), decoded.height()), (200, 50));
+        assert!(matches!(decoded, DynamicImage::ImageLuma8(_)));
+
+        let options = ImageOptions {
+            format: UploadFormat::Png,
+            ..ImageOptions::default()
+        };
+        let encoded = options.encode(&img);
+        assert_eq!(encoded.mime, "image/png");
+        // PNG 是无损的
+        assert_eq!(decode(&encoded).to_rgb8(), img);
+
+        let options = ImageOptions {
+            format: UploadFormat::Webp,
+            ..ImageOptions::default()
+        };
+        let encoded = options.encode(&img);
+        assert!(
+            encoded
+                .data_url()
+                .starts_with("data:image/webp;base64,UklGR")
+        );
+        assert_eq!(decode(&encoded).to_rgb8(), img);
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(Provider::OpenAi, 1024, 1024), 765);
+        assert_eq!(estimate_tokens(Provider::OpenAi, 2048, 4096), 1105);
+        assert_eq!(estimate_tokens(Provider::Anthropic, 1000, 1000), 1334);
+        assert_eq!(estimate_tokens(Provider::Gemini, 300, 300), 258);
+        assert_eq!(estimate_tokens(Provider::Gemini, 1000, 1000), 1032);
+        assert_eq!(estimate_tokens(Provider::Qwen, 280, 560), 202);
+    }
+}
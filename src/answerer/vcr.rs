@@ -0,0 +1,309 @@
+//! 记录和重放模型接口的请求，离线测试提示词、回答的解析以及用量统计
+//!
+//! 每次请求是 JSON Lines 中的一行，按模型和请求中所有图片的哈希匹配。
+//! 同一道题的多次请求（例如自我检查的第二轮）按记录的顺序重放。
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use reqwest::{
+    Url,
+    blocking::{RequestBuilder, Response},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{context::Context, prompt::fnv1a};
+
+use super::http::{Retry, send};
+
+/// 记录的一次请求和响应
+#[derive(Debug, Serialize, Deserialize)]
+struct Interaction {
+    model: String,
+    /// 请求中所有图片的哈希，按出现的顺序用逗号分隔
+    images: String,
+    /// 去掉 API KEY 的地址，只用于查看
+    url: String,
+    /// 图片替换为哈希的请求体，只用于查看
+    request: serde_json::Value,
+    /// 原始的响应体，流式回答时是完整的 SSE
+    response: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Record,
+    Replay,
+}
+
+/// 记录或者重放请求
+#[derive(Debug)]
+pub struct Vcr {
+    path: PathBuf,
+    mode: Mode,
+    /// 重放时还没有使用的记录，按模型和图片的哈希分组；记录时只用于串行写入
+    pending: Mutex<HashMap<(String, String), VecDeque<Interaction>>>,
+}
+
+impl Vcr {
+    pub fn from_args(ctx: &Context) -> Option<Self> {
+        if let Some(path) = &ctx.api_record {
+            return Some(Self::record(path));
+        }
+        ctx.api_replay.as_deref().map(Self::replay)
+    }
+
+    /// 追加到已有的记录之后
+    pub fn record(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            mode: Mode::Record,
+            pending: Mutex::default(),
+        }
+    }
+
+    pub fn replay(path: &Path) -> Self {
+        let text = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()));
+        let mut pending: HashMap<_, VecDeque<_>> = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let interaction: Interaction = serde_json::from_str(line)
+                .unwrap_or_else(|e| panic!("invalid record at {}:{}: {e}", path.display(), i + 1));
+            pending
+                .entry((interaction.model.clone(), interaction.images.clone()))
+                .or_default()
+                .push_back(interaction);
+        }
+        log::info!(
+            "replay {} requests from {}",
+            pending.values().map(VecDeque::len).sum::<usize>(),
+            path.display()
+        );
+        Self {
+            path: path.to_owned(),
+            mode: Mode::Replay,
+            pending: Mutex::new(pending),
+        }
+    }
+
+    /// 重放时不发送请求，记录时只记录成功的响应
+    pub(super) fn send(
+        &self,
+        retry: &Retry,
+        request: impl Fn() -> RequestBuilder,
+    ) -> Option<Response> {
+        let built = request().build().expect("invalid request");
+        let mut body = built
+            .body()
+            .and_then(|x| x.as_bytes())
+            .and_then(|x| serde_json::from_slice(x).ok())
+            .unwrap_or(serde_json::Value::Null);
+        let mut images = vec![];
+        redact_images(&mut body, &mut images);
+        let model = model(built.url(), &body);
+        let images = images.join(",");
+
+        if self.mode == Mode::Replay {
+            let mut pending = self.pending.lock().unwrap();
+            let Some(interaction) = pending
+                .get_mut(&(model.clone(), images.clone()))
+                .and_then(VecDeque::pop_front)
+            else {
+                log::warn!("No recorded response for model {model}, images {images}");
+                return None;
+            };
+            log::debug!("replay a response of {model}");
+            return Some(response(interaction.response));
+        }
+
+        let text = send(retry, request)?
+            .text()
+            .inspect_err(|e| log::warn!("Failed to read the response: {e}"))
+            .ok()?;
+        let interaction = Interaction {
+            model,
+            images,
+            url: redact_url(built.url()),
+            request: body,
+            response: text,
+        };
+        let _guard = self.pending.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .unwrap_or_else(|e| panic!("failed to open {}: {e}", self.path.display()));
+        writeln!(file, "{}", serde_json::to_string(&interaction).unwrap()).unwrap();
+        Some(response(interaction.response))
+    }
+}
+
+/// 请求体中的模型，Gemini 的模型在地址中，例如 `models/gemini-2.5-flash:generateContent`
+fn model(url: &Url, body: &serde_json::Value) -> String {
+    if let Some(model) = body["model"].as_str() {
+        return model.to_owned();
+    }
+    let segment = url.path_segments().and_then(|mut x| x.next_back());
+    let segment = segment.unwrap_or_default();
+    segment.split(':').next().unwrap_or_default().to_owned()
+}
+
+/// 把 base64 编码的图片替换为哈希，并按顺序收集这些哈希
+fn redact_images(value: &mut serde_json::Value, hashes: &mut Vec<String>) {
+    match value {
+        serde_json::Value::String(text) => {
+            let data = text.rsplit(";base64,").next().unwrap_or_default();
+            // JPEG、PNG 和 WebP 的文件头
+            if ["/9j/", "iVBORw0KGgo", "UklGR"]
+                .iter()
+                .any(|x| data.starts_with(x))
+            {
+                let hash = format!("{:016x}", fnv1a(data.as_bytes()));
+                *text = format!("<image {hash}>");
+                hashes.push(hash);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            items.iter_mut().for_each(|x| redact_images(x, hashes));
+        }
+        serde_json::Value::Object(map) => {
+            map.values_mut().for_each(|x| redact_images(x, hashes));
+        }
+        _ => {}
+    }
+}
+
+/// 去掉查询参数中的 API KEY，请求头不会被记录
+fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    let pairs: Vec<_> = url
+        .query_pairs()
+        .map(|(k, v)| {
+            let v = if k == "key" { "REDACTED".into() } else { v };
+            (k.into_owned(), v.into_owned())
+        })
+        .collect();
+    if !pairs.is_empty() {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+fn response(body: String) -> Response {
+    http::Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use image::{Rgb, RgbImage};
+    use serde_json::json;
+
+    use crate::{
+        answerer::{
+            Answer, Answerer, Endpoint, Multimodal, ReasoningEffort, ReasoningStyle,
+            mock::MockServer, multimodal::Reasoning,
+        },
+        compose::Question,
+        logging,
+    };
+
+    use super::*;
+
+    fn question(color: u8) -> Question {
+        Question {
+            images: vec![RgbImage::from_pixel(8, 8, Rgb([color; 3]))],
+            ..Question::for_test()
+        }
+    }
+
+    fn multimodal(url: String, vcr: Arc<Vcr>) -> Multimodal {
+        let endpoint = Endpoint {
+            vcr: Some(vcr),
+            ..Endpoint::for_test(url)
+        };
+        let reasoning = Reasoning {
+            style: ReasoningStyle::None,
+            thinking: false,
+            effort: ReasoningEffort::Medium,
+            budget: 1024,
+        };
+        Multimodal::new(endpoint, reasoning, false, false)
+    }
+
+    #[test]
+    fn test_record_replay() {
+        logging::init_for_test();
+        let path = std::env::temp_dir().join(format!("vcr-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let resp = |content: &str| {
+            json!({
+                "choices": [{"message": {"content": content}}],
+                "usage": {"prompt_tokens": 700, "completion_tokens": 2},
+            })
+            .to_string()
+        };
+        let server = MockServer::start(vec![(200, resp("B")), (200, resp("D"))]);
+        let url = server.url("/v1/chat/completions?key=sk-test");
+        let mut recorder = multimodal(url.clone(), Arc::new(Vcr::record(&path)));
+        assert_eq!(recorder.answer(&question(0)).unwrap().answer, Answer::B);
+        assert_eq!(recorder.answer(&question(255)).unwrap().answer, Answer::D);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(!text.contains("sk-test"));
+        assert!(!text.contains("/9j/"));
+
+        // 服务已经退出，只能从记录中重放
+        let mut player = multimodal(url, Arc::new(Vcr::replay(&path)));
+        assert_eq!(player.answer(&question(255)).unwrap().answer, Answer::D);
+        assert_eq!(player.answer(&question(0)).unwrap().answer, Answer::B);
+        assert!(player.answer(&question(0)).is_none());
+        assert!(player.answer(&question(128)).is_none());
+        let usage = player.usage();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (1400, 4));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_shared_replay() {
+        logging::init_for_test();
+        let path = std::env::temp_dir().join(format!("vcr-shared-{}.jsonl", std::process::id()));
+        let resp = |content: &str| json!({"choices": [{"message": {"content": content}}]});
+        let lines = [resp("B"), resp("D")].map(|response| {
+            let interaction = Interaction {
+                model: "test-model".to_owned(),
+                images: String::new(),
+                url: String::new(),
+                request: serde_json::Value::Null,
+                response: response.to_string(),
+            };
+            serde_json::to_string(&interaction).unwrap()
+        });
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        // 级联中同一个模型的两次回答按记录的顺序重放，而不是各自从头重放
+        let vcr = Arc::new(Vcr::replay(&path));
+        let mut cheap = multimodal("http://localhost".to_owned(), vcr.clone());
+        let mut strong = multimodal("http://localhost".to_owned(), vcr);
+        let mut question = question(0);
+        question.images.clear();
+        assert_eq!(cheap.answer(&question).unwrap().answer, Answer::B);
+        assert_eq!(strong.answer(&question).unwrap().answer, Answer::D);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
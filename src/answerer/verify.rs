@@ -0,0 +1,171 @@
+use crate::compose::Question;
+
+use super::{Answerer, Reply, Usage};
+
+/// 自我检查：第一轮先分析再回答，第二轮在同一段对话中让模型检查答案
+pub struct Verify {
+    /// 用于区分日志中的多个模型
+    name: String,
+    inner: Box<dyn Answerer>,
+
+    questions: u32,
+    /// 第二轮改变了答案的题数
+    changed: u32,
+    /// 第二轮没有给出有效答案的题数，使用第一轮的答案
+    failed: u32,
+    first_pass: Usage,
+    second_pass: Usage,
+}
+
+impl Verify {
+    pub fn new(name: String, inner: Box<dyn Answerer>) -> Self {
+        Self {
+            name,
+            inner,
+            questions: 0,
+            changed: 0,
+            failed: 0,
+            first_pass: Usage::default(),
+            second_pass: Usage::default(),
+        }
+    }
+
+    /// 回答并累计这一轮的用量
+    fn pass(&mut self, question: &Question, second: bool) -> Option<Reply> {
+        let before = self.inner.usage();
+        let reply = self.inner.answer(question);
+        let usage = self.inner.usage();
+        let pass = if second {
+            &mut self.second_pass
+        } else {
+            &mut self.first_pass
+        };
+        *pass = *pass
+            + Usage {
+                input_tokens: usage.input_tokens - before.input_tokens,
+                output_tokens: usage.output_tokens - before.output_tokens,
+                reasoning_tokens: usage.reasoning_tokens - before.reasoning_tokens,
+                cost: usage.cost - before.cost,
+            };
+        reply
+    }
+}
+
+impl Answerer for Verify {
+    fn answer(&mut self, question: &Question) -> Option<Reply> {
+        let first = self.pass(question, false)?;
+        // 各个后端都通过 `parse_reply` 保留了回答的原文
+        let message = first
+            .message
+            .clone()
+            .expect("backend reply without the original message");
+        self.questions += 1;
+        let follow_up = Question {
+            follow_up: Some(message),
+            ..question.clone()
+        };
+        let Some(second) = self.pass(&follow_up, true) else {
+            self.failed += 1;
+            log::debug!("verify: no valid answer in the second pass, use the first one");
+            return Some(first);
+        };
+        if second.answer != first.answer {
+            self.changed += 1;
+            log::info!(
+                "verify: answer changed from {} to {}",
+                first.answer.to_str(),
+                second.answer.to_str()
+            );
+        }
+        Some(second)
+    }
+
+    fn usage(&self) -> Usage {
+        self.inner.usage()
+    }
+
+    fn usage_details(&self) -> Vec<(String, Usage)> {
+        vec![
+            ("first pass".to_owned(), self.first_pass),
+            ("verify pass".to_owned(), self.second_pass),
+        ]
+    }
+
+    fn log_summary(&self) {
+        let rate = if self.questions == 0 {
+            0.0
+        } else {
+            self.changed as f32 / self.questions as f32
+        };
+        log::info!(
+            "verify: {}: questions: {}, changed: {} ({:.3}), failed: {}, tokens: first pass: {}, verify pass: {}",
+            self.name,
+            self.questions,
+            self.changed,
+            rate,
+            self.failed,
+            self.first_pass.tokens(),
+            self.second_pass.tokens()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::answerer::{Answer, parse_reply};
+
+    use super::*;
+
+    /// 按顺序返回预设的文字回答，每次使用 100 个输入 token
+    struct Scripted {
+        messages: Vec<&'static str>,
+        follow_ups: Vec<Option<String>>,
+    }
+
+    impl Answerer for Scripted {
+        fn answer(&mut self, question: &Question) -> Option<Reply> {
+            self.follow_ups.push(question.follow_up.clone());
+            // 第二轮带着第一轮回答的原文
+            if self.follow_ups.len().is_multiple_of(2) {
+                let first = self.messages[self.follow_ups.len() - 2];
+                assert_eq!(question.follow_up.as_deref(), Some(first));
+            }
+            parse_reply(self.messages[self.follow_ups.len() - 1])
+        }
+
+        fn usage(&self) -> Usage {
+            Usage {
+                input_tokens: self.follow_ups.len() as u64 * 100,
+                ..Usage::default()
+            }
+        }
+    }
+
+    fn question() -> Question {
+        Question {
+            verify: true,
+            ..Question::for_test()
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let first = "A 和 C 都不对，B 更符合题意。\n答案：B";
+        let scripted = Scripted {
+            messages: vec![first, "D", "答案：A", "无法确定"],
+            follow_ups: vec![],
+        };
+        let mut verify = Verify::new("test-model".to_owned(), Box::new(scripted));
+
+        let reply = verify.answer(&question()).unwrap();
+        assert_eq!(reply.answer, Answer::D);
+        // 第二轮无效时使用第一轮的答案
+        let reply = verify.answer(&question()).unwrap();
+        assert_eq!(reply.answer, Answer::A);
+
+        assert_eq!((verify.questions, verify.changed, verify.failed), (2, 1, 1));
+        assert_eq!(verify.first_pass.input_tokens, 200);
+        assert_eq!(verify.second_pass.input_tokens, 200);
+        assert_eq!(verify.usage().input_tokens, 400);
+    }
+}
@@ -0,0 +1,437 @@
+//! 本地题库，记录回答过的题目，再次遇到时不再询问模型
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use image::{RgbImage, RgbaImage, imageops};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    answerer::Answer,
+    compose::{QuestionText, crop, options, stem},
+    context::Context,
+    page::PageQuestion,
+};
+
+/// 256 位的差值哈希，相似的图片的汉明距离较小
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PHash([u64; 4]);
+
+impl PHash {
+    /// 文字是横向排列的，横向取 32 列，纵向取 8 行
+    const W: u32 = 32;
+    const H: u32 = 8;
+
+    pub fn new(img: &RgbImage) -> Self {
+        let gray = imageops::grayscale(img);
+        let small = imageops::resize(&gray, Self::W + 1, Self::H, imageops::FilterType::Triangle);
+        let mut bits = [0u64; 4];
+        for y in 0..Self::H {
+            for x in 0..Self::W {
+                let i = (y * Self::W + x) as usize;
+                if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                    bits[i / 64] |= 1 << (i % 64);
+                }
+            }
+        }
+        Self(bits)
+    }
+
+    /// 只计算框内文字所在的区域，选项的框和留白占了大部分面积，
+    /// 直接计算时文字不同的选项的哈希也很接近
+    pub fn of_content(img: &RgbImage) -> Self {
+        let gray = imageops::grayscale(img);
+        let (w, h) = gray.dimensions();
+        // 跳过边框
+        let (x0, y0) = (w / 12, h / 8);
+        let (x1, y1) = (w - x0, h - y0);
+        let background = gray.get_pixel(x0, y0)[0] as i32;
+        let (mut left, mut top, mut right, mut bottom) = (x1, y1, x0, y0);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                if (gray.get_pixel(x, y)[0] as i32 - background).abs() > 32 {
+                    (left, top) = (left.min(x), top.min(y));
+                    (right, bottom) = (right.max(x + 1), bottom.max(y + 1));
+                }
+            }
+        }
+        if left >= right || top >= bottom {
+            return Self::new(img);
+        }
+        Self::new(&imageops::crop_imm(img, left, top, right - left, bottom - top).to_image())
+    }
+
+    pub fn distance(&self, other: &Self) -> u32 {
+        self.0
+            .iter()
+            .zip(other.0)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
+
+impl Serialize for PHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = self.0.iter().map(|x| format!("{x:016x}")).collect();
+        serializer.serialize_str(&hex)
+    }
+}
+
+impl<'de> Deserialize<'de> for PHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(serde::de::Error::custom(format!("invalid hash: {hex}")));
+        }
+        let mut bits = [0u64; 4];
+        for (i, x) in bits.iter_mut().enumerate() {
+            *x = u64::from_str_radix(&hex[i * 16..i * 16 + 16], 16)
+                .map_err(serde::de::Error::custom)?;
+        }
+        Ok(Self(bits))
+    }
+}
+
+/// 一道题的特征：题干和每个选项的哈希，以及题干和选项的文字
+#[derive(Clone, Debug)]
+pub struct Fingerprint {
+    pub stem: PHash,
+    pub options: Vec<PHash>,
+    pub text: Option<String>,
+    /// 与 `options` 的顺序相同
+    pub option_texts: Vec<String>,
+}
+
+impl Fingerprint {
+    pub fn new(screen: &RgbaImage, page: &PageQuestion, text: Option<&QuestionText>) -> Self {
+        let mut fingerprint = Self {
+            stem: PHash::new(&crop(screen, &stem(page))),
+            options: options(screen, page)
+                .iter()
+                .map(PHash::of_content)
+                .collect(),
+            text: None,
+            option_texts: vec![],
+        };
+        if let Some(text) = text {
+            fingerprint.set_text(text);
+        }
+        fingerprint
+    }
+
+    pub fn set_text(&mut self, text: &QuestionText) {
+        self.text = Some(normalize(&text.stem));
+        self.option_texts = text.options.clone();
+    }
+}
+
+/// 去掉空白和标点并转为小写，只保留文字和数字
+pub fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// 题库中的一条记录，选项的顺序每次可能不同，答案记录为选项的哈希
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub stem: PHash,
+    pub options: Vec<PHash>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// 模型转写的选项的文字，与 `options` 的顺序相同
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub option_texts: Vec<String>,
+    /// 选择的选项的哈希
+    pub answer: PHash,
+    /// 答案是否正确，不知道时为空，可以手动修改，错误的记录不会被使用
+    #[serde(default)]
+    pub correct: Option<bool>,
+    /// 记录的时间
+    #[serde(default)]
+    pub time: String,
+}
+
+/// JSON Lines 格式的题库，每行一条记录，同一道题后面的记录优先
+pub struct Bank {
+    path: PathBuf,
+    entries: Vec<Entry>,
+    /// 认为是同一张图片的最大汉明距离
+    distance: u32,
+
+    hits: u32,
+    misses: u32,
+}
+
+impl Bank {
+    pub fn from_args(ctx: &Context) -> Option<Self> {
+        let path = ctx.question_bank.as_ref()?;
+        Some(Self::open(path, ctx.question_bank_distance))
+    }
+
+    pub fn open(path: &Path, distance: u32) -> Self {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(content) => content
+                .lines()
+                .filter(|x| !x.trim().is_empty())
+                .filter_map(|line| {
+                    serde_json::from_str(line)
+                        .inspect_err(|e| log::warn!("Invalid question bank entry: {e}: {line}"))
+                        .ok()
+                })
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => panic!("failed to read {}: {e}", path.display()),
+        };
+        log::info!(
+            "question bank: {} entries from {}",
+            entries.len(),
+            path.display()
+        );
+        Self {
+            path: path.to_owned(),
+            entries,
+            distance,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// 查找同一道题，返回答案在当前选项中的位置
+    pub fn lookup(&mut self, fingerprint: &Fingerprint) -> Option<Answer> {
+        let answer = self.find(fingerprint);
+        match answer {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        answer
+    }
+
+    fn find(&self, fingerprint: &Fingerprint) -> Option<Answer> {
+        let candidates = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|x| x.correct != Some(false))
+            .filter(|x| self.same_question(x, fingerprint));
+        // 确认正确的记录优先，其次是最新的记录
+        let entry = candidates.min_by_key(|x| x.correct != Some(true))?;
+        let index = self.nearest(&entry.answer, &fingerprint.options);
+        log::debug!(
+            "question bank: answer option {index:?}, correct: {:?}",
+            entry.correct
+        );
+        index.map(Answer::from_index)
+    }
+
+    /// `options` 中与 `hash` 相近的唯一一个选项
+    ///
+    /// 选项大多是空白的框，文字长度相同的选项的哈希也很接近，
+    /// 有多个选项在阈值之内时无法确定是哪一个，不使用记录
+    fn nearest(&self, hash: &PHash, options: &[PHash]) -> Option<usize> {
+        let mut distances: Vec<_> = options
+            .iter()
+            .map(|x| x.distance(hash))
+            .enumerate()
+            .collect();
+        distances.sort_by_key(|x| x.1);
+        let (index, best) = *distances.first()?;
+        let second = distances.get(1).map_or(u32::MAX, |x| x.1);
+        log::trace!("question bank: nearest option {index}, distance: {best}, second: {second}");
+        (best <= self.distance && second > self.distance).then_some(index)
+    }
+
+    /// 之前转写过同一道题时返回题目的文字，选项按当前的顺序排列
+    ///
+    /// 不考虑答案是否正确，标记为错误的记录中的文字同样可以使用
+    pub fn transcript(&self, fingerprint: &Fingerprint) -> Option<QuestionText> {
+        let entry = self.entries.iter().rev().find(|x| {
+            x.text.is_some()
+                && x.option_texts.len() == x.options.len()
+                && self.same_question(x, fingerprint)
+        })?;
+        let options = fingerprint
+            .options
+            .iter()
+            .map(|x| {
+                let index = self.nearest(x, &entry.options)?;
+                Some(entry.option_texts[index].clone())
+            })
+            .collect::<Option<_>>()?;
+        Some(QuestionText {
+            stem: entry.text.clone()?,
+            options,
+        })
+    }
+
+    /// 题干的哈希相近或者文字相同，并且每个选项都能找到相近的选项
+    fn same_question(&self, entry: &Entry, fingerprint: &Fingerprint) -> bool {
+        let same_text = entry.text.is_some() && entry.text == fingerprint.text;
+        if !same_text && entry.stem.distance(&fingerprint.stem) > self.distance {
+            return false;
+        }
+        entry.options.len() == fingerprint.options.len()
+            && fingerprint
+                .options
+                .iter()
+                .all(|x| entry.options.iter().any(|y| x.distance(y) <= self.distance))
+    }
+
+    /// 记录模型的答案并追加到文件中
+    pub fn record(&mut self, fingerprint: &Fingerprint, answer: Answer) {
+        let entry = Entry {
+            stem: fingerprint.stem,
+            options: fingerprint.options.clone(),
+            text: fingerprint.text.clone(),
+            option_texts: fingerprint.option_texts.clone(),
+            answer: fingerprint.options[answer as usize],
+            correct: None,
+            time: chrono::Local::now().to_rfc3339(),
+        };
+        let line = serde_json::to_string(&entry).unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .unwrap_or_else(|e| panic!("failed to open {}: {e}", self.path.display()));
+        writeln!(file, "{line}").unwrap();
+        self.entries.push(entry);
+    }
+
+    pub fn log_summary(&self) {
+        log::info!(
+            "question bank: hits: {}, misses: {}, entries: {}",
+            self.hits,
+            self.misses,
+            self.entries.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{question_screen, question_screen_with_options};
+
+    use super::*;
+
+    fn fingerprint(lines: usize) -> Fingerprint {
+        let screen = question_screen(lines);
+        let page = PageQuestion {
+            core: screen.card,
+            check_boxes: screen.options,
+        };
+        Fingerprint::new(&screen.image, &page, None)
+    }
+
+    #[test]
+    fn test_bank() {
+        let path = std::env::temp_dir().join(format!("question-bank-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut bank = Bank::open(&path, 16);
+        let question = fingerprint(3);
+        assert!(bank.lookup(&question).is_none());
+        bank.record(&question, Answer::B);
+        assert!(matches!(bank.lookup(&question), Some(Answer::B)));
+
+        // 选项的顺序改变时答案跟随选项移动
+        let mut shuffled = question.clone();
+        shuffled.options.rotate_left(1);
+        assert!(matches!(bank.lookup(&shuffled), Some(Answer::A)));
+
+        let other = fingerprint(5);
+        assert!(other.stem.distance(&question.stem) > 16);
+        assert!(bank.lookup(&other).is_none());
+
+        // 重新打开时从文件中读取
+        let mut bank = Bank::open(&path, 16);
+        assert_eq!(bank.entries.len(), 1);
+        assert!(matches!(bank.lookup(&question), Some(Answer::B)));
+
+        // 标记为错误的记录不会被使用
+        bank.entries[0].correct = Some(false);
+        assert!(bank.lookup(&question).is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_transcript() {
+        let path =
+            std::env::temp_dir().join(format!("question-bank-text-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut bank = Bank::open(&path, 16);
+        let mut question = fingerprint(3);
+        assert!(bank.transcript(&question).is_none());
+        question.set_text(&QuestionText {
+            stem: "《红楼梦》的作者是？".to_owned(),
+            options: ["曹雪芹", "罗贯中", "施耐庵", "吴承恩"]
+                .map(str::to_owned)
+                .to_vec(),
+        });
+        bank.record(&question, Answer::B);
+        let mut bank = Bank::open(&path, 16);
+        bank.entries[0].correct = Some(false);
+
+        // 截图相同但还没有转写，选项的顺序改变，答案错误的记录中的文字也可以使用
+        let mut shuffled = fingerprint(3);
+        shuffled.options.rotate_left(1);
+        assert!(bank.lookup(&shuffled).is_none());
+        let text = bank.transcript(&shuffled).unwrap();
+        assert_eq!(text.stem, "红楼梦的作者是");
+        assert_eq!(text.options, ["罗贯中", "施耐庵", "吴承恩", "曹雪芹"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_phash_serde() {
+        let hash = PHash([0, 1, u64::MAX, 0x0123_4567_89ab_cdef]);
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(
+            json,
+            "\"00000000000000000000000000000001ffffffffffffffff0123456789abcdef\""
+        );
+        assert_eq!(serde_json::from_str::<PHash>(&json).unwrap(), hash);
+        assert!(serde_json::from_str::<PHash>("\"0123\"").is_err());
+    }
+
+    #[test]
+    fn test_option_hash() {
+        let question = fingerprint(3);
+        for (i, a) in question.options.iter().enumerate() {
+            for b in &question.options[i + 1..] {
+                assert!(a.distance(b) > 16, "{}", a.distance(b));
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_length_options() {
+        let path =
+            std::env::temp_dir().join(format!("question-bank-same-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let fingerprint = |options: &[&str; 4]| {
+            let screen = question_screen_with_options(3, options);
+            let page = PageQuestion {
+                core: screen.card,
+                check_boxes: screen.options,
+            };
+            Fingerprint::new(&screen.image, &page, None)
+        };
+        // 四个选项的文字长度相同，只有个别字不同
+        let options = ["xxxxxx", "xxx xx", "x xxxx", "xxxx x"];
+        let question = fingerprint(&options);
+        let mut bank = Bank::open(&path, 16);
+        bank.record(&question, Answer::B);
+
+        // 顺序改变后原来的 B 在 C，哈希无法区分时不能选择其他选项
+        let shuffled = fingerprint(&[options[0], options[2], options[1], options[3]]);
+        let answer = bank.lookup(&shuffled);
+        assert!(matches!(answer, None | Some(Answer::C)), "{answer:?}");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,327 @@
+//! 社区整理的题目列表，按题目的文字查找答案
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{answerer::Answer, bank::normalize, compose::QuestionText, context::Context};
+
+/// 列表中的一道题，`answer` 可以是答案的文字，也可以是选项字母
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    #[serde(alias = "题目", alias = "题干")]
+    pub question: String,
+    #[serde(default, alias = "选项")]
+    pub options: Vec<String>,
+    #[serde(alias = "答案")]
+    pub answer: String,
+}
+
+impl Record {
+    /// 记录的答案是选项的文字，不是选项字母
+    pub fn new(text: &QuestionText, answer: Answer) -> Self {
+        Self {
+            question: text.stem.clone(),
+            options: text.options.clone(),
+            answer: text
+                .options
+                .get(answer as usize)
+                .cloned()
+                .unwrap_or(answer.to_str().to_owned()),
+        }
+    }
+
+    /// 答案的文字，答案是选项字母时换成对应选项的文字
+    fn answer_text(&self) -> Option<&str> {
+        let letter = match self.answer.trim().to_ascii_uppercase().as_str() {
+            "A" => Some(0),
+            "B" => Some(1),
+            "C" => Some(2),
+            "D" => Some(3),
+            _ => None,
+        };
+        match letter {
+            Some(i) => self.options.get(i).map(String::as_str),
+            None => Some(&self.answer),
+        }
+    }
+}
+
+/// 导入的题目列表
+pub struct QuestionList {
+    records: Vec<Record>,
+    /// 题目和答案的文字相似度的下限
+    similarity: f32,
+}
+
+impl QuestionList {
+    pub fn from_args(ctx: &Context) -> Option<Self> {
+        if ctx.community_list.is_empty() {
+            return None;
+        }
+        let records = ctx.community_list.iter().flat_map(|x| load(x)).collect();
+        Some(Self::new(records, ctx.community_similarity))
+    }
+
+    pub fn new(records: Vec<Record>, similarity: f32) -> Self {
+        log::info!("community list: {} questions", records.len());
+        Self {
+            records,
+            similarity,
+        }
+    }
+
+    /// 按题干找到最相似的题目，再按答案的文字找到它在当前选项中的位置
+    pub fn lookup(&self, text: &QuestionText) -> Option<Answer> {
+        let stem = normalize(&text.stem);
+        let (record, score) = self
+            .records
+            .iter()
+            .map(|x| (x, similarity(&normalize(&x.question), &stem)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        if score < self.similarity {
+            return None;
+        }
+        let answer = normalize(record.answer_text()?);
+        let (index, score) = text
+            .options
+            .iter()
+            .map(|x| similarity(&normalize(x), &answer))
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        log::debug!(
+            "community list: {:?}, answer: {:?}, option {index}, similarity: {score:.2}",
+            record.question,
+            record.answer
+        );
+        (score >= self.similarity && index < 4).then(|| Answer::from_index(index))
+    }
+}
+
+/// 字符二元组的 Dice 系数，过短的文字只比较是否相同
+fn similarity(a: &str, b: &str) -> f32 {
+    if a == b {
+        return 1.0;
+    }
+    let bigrams = |x: &str| {
+        let chars: Vec<_> = x.chars().collect();
+        let mut bigrams: Vec<_> = chars.windows(2).map(|x| (x[0], x[1])).collect();
+        bigrams.sort_unstable();
+        bigrams
+    };
+    let (a, b) = (bigrams(a), bigrams(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    // 两个有序列表的交集，重复的二元组按次数计算
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    2.0 * common as f32 / (a.len() + b.len()) as f32
+}
+
+fn is_csv(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("csv"))
+}
+
+/// 按扩展名读取 CSV 或者 JSON 格式的列表
+pub fn load(path: &Path) -> Vec<Record> {
+    let content = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()));
+    let content = content.trim_start_matches('\u{feff}');
+    let records = if is_csv(path) {
+        from_csv(content)
+    } else {
+        serde_json::from_str(content)
+            .unwrap_or_else(|e| panic!("failed to parse {}: {e}", path.display()))
+    };
+    log::debug!("{}: {} questions", path.display(), records.len());
+    records
+}
+
+/// 将回答的题目合并到已有的列表中，同一道题使用新的记录
+///
+/// 每回答一道题就写入一次，中途退出时已经回答的题目不会丢失
+pub fn export(path: &Path, records: &[Record]) {
+    let mut all = if path.exists() { load(path) } else { vec![] };
+    for record in records {
+        let question = normalize(&record.question);
+        all.retain(|x| normalize(&x.question) != question);
+        all.push(record.clone());
+    }
+    let content = if is_csv(path) {
+        to_csv(&all)
+    } else {
+        serde_json::to_string_pretty(&all).unwrap()
+    };
+    std::fs::write(path, content)
+        .unwrap_or_else(|e| panic!("failed to write {}: {e}", path.display()));
+    log::debug!(
+        "Exported {} questions to {}, {} in total",
+        records.len(),
+        path.display(),
+        all.len()
+    );
+}
+
+/// 表头中 question/题目/题干 为题干，answer/答案 为答案，a/b/c/d 或者 选项a/option_a 等为选项
+fn from_csv(content: &str) -> Vec<Record> {
+    let mut rows = parse_csv(content).into_iter();
+    let Some(header) = rows.next() else {
+        return vec![];
+    };
+    let column = |names: &[&str]| {
+        header.iter().position(|x| {
+            let x = x.trim().to_lowercase();
+            names.contains(&x.as_str())
+        })
+    };
+    let question = column(&["question", "题目", "题干"]).expect("CSV has no question column");
+    let answer = column(&["answer", "答案"]).expect("CSV has no answer column");
+    let options: Vec<_> = ["a", "b", "c", "d"]
+        .iter()
+        .filter_map(|x| column(&[*x, &format!("选项{x}"), &format!("option_{x}")]))
+        .collect();
+    rows.filter(|row| row.iter().any(|x| !x.trim().is_empty()))
+        .map(|row| {
+            let cell = |i: usize| row.get(i).map_or("", |x| x.trim()).to_owned();
+            let mut options: Vec<_> = options.iter().map(|i| cell(*i)).collect();
+            if options.iter().all(String::is_empty) {
+                options.clear();
+            }
+            Record {
+                question: cell(question),
+                options,
+                answer: cell(answer),
+            }
+        })
+        .collect()
+}
+
+/// 支持双引号包围的字段，字段中的双引号写作两个双引号
+fn parse_csv(content: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+fn to_csv(records: &[Record]) -> String {
+    let escape = |x: &str| {
+        if x.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", x.replace('"', "\"\""))
+        } else {
+            x.to_owned()
+        }
+    };
+    let mut csv = String::from("question,a,b,c,d,answer\n");
+    for record in records {
+        let mut cells = vec![escape(&record.question)];
+        cells.extend((0..4).map(|i| escape(record.options.get(i).map_or("", |x| x.as_str()))));
+        cells.push(escape(&record.answer));
+        csv.push_str(&cells.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(stem: &str, options: [&str; 4]) -> QuestionText {
+        QuestionText {
+            stem: stem.to_owned(),
+            options: options.map(str::to_owned).to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let csv = "\u{feff}题目,答案,选项A,选项B,选项C,选项D\n\
+            \"以下哪个不是“三原色”之一？\",绿色,红色,黄色,蓝色,绿色\n\
+            \"《红楼梦》的作者是\",A,\"曹雪芹\",罗贯中,施耐庵,吴承恩\r\n";
+        let list = QuestionList::new(from_csv(csv.trim_start_matches('\u{feff}')), 0.8);
+        assert_eq!(list.records.len(), 2);
+
+        // 选项的顺序不同，题干的标点和空白不同
+        let question = text(
+            "以下哪个不是 “三原色” 之一",
+            ["蓝色", "绿色", "红色", "黄色"],
+        );
+        assert!(matches!(list.lookup(&question), Some(Answer::B)));
+        let question = text(
+            "《红楼梦》的作者是？",
+            ["吴承恩", "施耐庵", "罗贯中", "曹雪芹"],
+        );
+        assert!(matches!(list.lookup(&question), Some(Answer::D)));
+
+        let question = text(
+            "《西游记》的作者是？",
+            ["吴承恩", "施耐庵", "罗贯中", "曹雪芹"],
+        );
+        assert!(list.lookup(&question).is_none());
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let records = vec![
+            Record {
+                question: "含有,逗号和\"引号\"的题目".to_owned(),
+                options: vec![
+                    "1".to_owned(),
+                    "2".to_owned(),
+                    "3".to_owned(),
+                    "4".to_owned(),
+                ],
+                answer: "2".to_owned(),
+            },
+            Record::new(&text("1 + 2 = ?", ["1", "2", "3", "4"]), Answer::C),
+        ];
+        assert_eq!(records[1].answer, "3");
+        assert_eq!(from_csv(&to_csv(&records)), records);
+
+        let json = r#"[{"题目": "1 + 1 = ?", "选项": ["1", "2", "3", "4"], "答案": "B"}]"#;
+        let records: Vec<Record> = serde_json::from_str(json).unwrap();
+        assert_eq!(records[0].answer_text(), Some("2"));
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("abc", "abc"), 1.0);
+        assert_eq!(similarity("a", "b"), 0.0);
+        assert!((similarity("abcd", "abce") - 2.0 * 2.0 / 6.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,317 @@
+use image::{GenericImageView, Rgb, RgbImage, RgbaImage, buffer::ConvertBuffer, imageops};
+use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+
+use std::sync::Arc;
+
+use crate::{answerer::Answer, few_shot::Example, page::PageQuestion, prompt::Prompt};
+
+/// 发送给模型的图片的组织方式
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageLayout {
+    /// 截取题干和选项所在的整块区域
+    #[default]
+    Core,
+    /// 分别截取题干和每个选项，拼接成一张在选项左侧标注 A/B/C/D 的紧凑图片
+    Stitch,
+    /// 题干和每个选项分别作为一张图片发送，选项按 A/B/C/D 的顺序排列
+    Parts,
+}
+
+/// 发送给模型的题目
+#[derive(Clone)]
+pub struct Question {
+    pub layout: ImageLayout,
+    /// 选项上是否标注了选项字母
+    pub labeled: bool,
+    pub images: Vec<RgbImage>,
+    pub option_count: usize,
+    /// 题目的文字，包括题干和选项
+    pub text: Option<QuestionText>,
+    /// 要求模型在回答时同时转写题目的文字，仅结构化输出时有效
+    pub transcribe: bool,
+    /// 自我检查模式，第一轮要求模型先分析再回答
+    pub verify: bool,
+    /// 自我检查时第一轮回答的原文，存在时在题目之后追加一轮检查答案的对话
+    pub follow_up: Option<String>,
+    /// 由模板渲染的提示词
+    pub prompt: Prompt,
+    /// 放在题目之前的少样本示例
+    pub examples: Arc<[Example]>,
+}
+
+/// 题目的文字
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QuestionText {
+    pub stem: String,
+    /// 按 A、B、C、D 的顺序排列
+    pub options: Vec<String>,
+}
+
+impl std::fmt::Display for QuestionText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.stem)?;
+        for (i, option) in self.options.iter().enumerate() {
+            write!(f, "\n{}. {}", Answer::from_index(i).to_str(), option)?;
+        }
+        Ok(())
+    }
+}
+
+impl Question {
+    /// 测试用的题目：一张 8x8 的图片，4 个选项，没有提示词和示例
+    #[cfg(test)]
+    pub(crate) fn for_test() -> Self {
+        Self {
+            layout: ImageLayout::Core,
+            labeled: false,
+            images: vec![RgbImage::new(8, 8)],
+            option_count: 4,
+            text: None,
+            transcribe: false,
+            verify: false,
+            follow_up: None,
+            prompt: Prompt::default(),
+            examples: Arc::default(),
+        }
+    }
+
+    /// `label` 为 true 时在每个选项的右侧标注选项字母，拼接的图片总是会标注在选项左侧
+    pub fn compose(
+        screen: &RgbaImage,
+        page: &PageQuestion,
+        layout: ImageLayout,
+        label: bool,
+    ) -> Self {
+        let mut options = options(screen, page);
+        if label && layout == ImageLayout::Parts {
+            for (i, option) in options.iter_mut().enumerate() {
+                let rect = Rect::at(0, 0).of_size(option.width(), option.height());
+                label_option(option, &rect, Answer::from_index(i));
+            }
+        }
+        let images = match layout {
+            ImageLayout::Core => {
+                let mut core = crop(screen, &page.core);
+                if label {
+                    for (i, rect) in page.check_boxes.iter().enumerate() {
+                        let rect =
+                            Rect::at(rect.left() - page.core.left(), rect.top() - page.core.top())
+                                .of_size(rect.width(), rect.height());
+                        label_option(&mut core, &rect, Answer::from_index(i));
+                    }
+                }
+                vec![core]
+            }
+            ImageLayout::Stitch => vec![stitch(&crop(screen, &stem(page)), &options)],
+            ImageLayout::Parts => {
+                let mut images = vec![crop(screen, &stem(page))];
+                images.extend(options);
+                images
+            }
+        };
+        Self {
+            layout,
+            labeled: label || layout == ImageLayout::Stitch,
+            images,
+            option_count: page.check_boxes.len(),
+            text: None,
+            transcribe: false,
+            prompt: Prompt::default(),
+            examples: Arc::default(),
+            verify: false,
+            follow_up: None,
+        }
+    }
+}
+
+/// 题干：题目区域中第一个选项以上的部分
+pub(crate) fn stem(page: &PageQuestion) -> Rect {
+    let core = &page.core;
+    let bottom = page.check_boxes[0].top().max(core.top() + 1);
+    Rect::at(core.left(), core.top()).of_size(core.width(), (bottom - core.top()) as u32)
+}
+
+pub(crate) fn options(screen: &RgbaImage, page: &PageQuestion) -> Vec<RgbImage> {
+    page.check_boxes.iter().map(|x| crop(screen, x)).collect()
+}
+
+pub(crate) fn crop(screen: &RgbaImage, rect: &Rect) -> RgbImage {
+    screen
+        .view(
+            rect.left() as u32,
+            rect.top() as u32,
+            rect.width(),
+            rect.height(),
+        )
+        .to_image()
+        .convert()
+}
+
+/// 拼接的图片中各部分之间的空白
+const GAP: u32 = 8;
+
+/// 题干在上，选项依次排在下面，每个选项左侧留出标注字母的位置
+fn stitch(stem: &RgbImage, options: &[RgbImage]) -> RgbImage {
+    let badge = badge_size(options.iter().map(|x| x.height()).min().unwrap_or(0));
+    let gutter = badge + GAP * 2;
+    let width = options
+        .iter()
+        .map(|x| x.width() + gutter)
+        .chain([stem.width()])
+        .max()
+        .unwrap();
+    let height = stem.height() + options.iter().map(|x| x.height() + GAP).sum::<u32>();
+
+    let mut img = RgbImage::from_pixel(width, height, WHITE);
+    imageops::replace(&mut img, stem, 0, 0);
+    let mut y = stem.height() + GAP;
+    for (i, option) in options.iter().enumerate() {
+        let top = y + option.height().saturating_sub(badge) / 2;
+        draw_badge(&mut img, GAP, top, badge, Answer::from_index(i));
+        imageops::replace(&mut img, option, gutter as i64, y as i64);
+        y += option.height() + GAP;
+    }
+    img
+}
+
+/// 在选项框内的右侧标注选项字母，选项的文字一般靠左，右侧是空白
+fn label_option(img: &mut RgbImage, rect: &Rect, answer: Answer) {
+    let badge = badge_size(rect.height());
+    let Some(pad) = rect.height().checked_sub(badge).map(|x| x / 2) else {
+        return;
+    };
+    if rect.width() < badge + pad * 2 || rect.right() as u32 >= img.width() {
+        return;
+    }
+    let x = rect.right() as u32 - pad - badge;
+    let y = rect.top().max(0) as u32 + pad;
+    if y + badge > img.height() {
+        return;
+    }
+    draw_badge(img, x, y, badge, answer);
+}
+
+const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+const BADGE: Rgb<u8> = Rgb([251, 114, 153]);
+
+/// 标注的边长，取选项高度的一半，并且是字模大小的整数倍
+fn badge_size(option_height: u32) -> u32 {
+    let cell = GLYPH_H as u32 + 2;
+    (option_height / 2 / cell).max(1) * cell
+}
+
+/// 在 `(x, y)` 处画一个边长为 `size` 的方块，方块中间是选项字母
+fn draw_badge(img: &mut RgbImage, x: u32, y: u32, size: u32, answer: Answer) {
+    let rect = Rect::at(x as i32, y as i32).of_size(size, size);
+    draw_filled_rect_mut(img, rect, BADGE);
+
+    let glyph = glyph(answer);
+    let pixel = size / (GLYPH_H as u32 + 2);
+    let left = x + (size - pixel * GLYPH_W as u32) / 2;
+    let top = y + (size - pixel * GLYPH_H as u32) / 2;
+    for (row, bits) in glyph.iter().enumerate() {
+        for col in 0..GLYPH_W {
+            if bits & (1 << (GLYPH_W - 1 - col)) == 0 {
+                continue;
+            }
+            let dot = Rect::at(
+                (left + col as u32 * pixel) as i32,
+                (top + row as u32 * pixel) as i32,
+            )
+            .of_size(pixel, pixel);
+            draw_filled_rect_mut(img, dot, WHITE);
+        }
+    }
+}
+
+const GLYPH_W: usize = 5;
+const GLYPH_H: usize = 7;
+
+/// 5x7 的点阵字模，每行的低 5 位从左到右
+fn glyph(answer: Answer) -> [u8; GLYPH_H] {
+    match answer {
+        Answer::A => [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        Answer::B => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ],
+        Answer::C => [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+        Answer::D => [
+            0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110,
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fixtures,
+        vision::{DEFAULT_BAND, Pipeline},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_compose() {
+        let screen = fixtures::question_screen(2);
+        let page = Pipeline::new(2, DEFAULT_BAND)
+            .detect(&screen.image)
+            .page
+            .unwrap();
+
+        let option = &page.check_boxes[1];
+        let badge = badge_size(option.height());
+        let pad = (option.height() - badge) / 2;
+
+        // 标注在选项框内的右侧
+        let core = Question::compose(&screen.image, &page, ImageLayout::Core, false);
+        assert_eq!(core.images.len(), 1);
+        let labeled = Question::compose(&screen.image, &page, ImageLayout::Core, true);
+        assert!(labeled.labeled);
+        let x = (option.right() - page.core.left()) as u32 - pad - badge;
+        let y = (option.top() - page.core.top()) as u32 + pad;
+        assert_eq!(*labeled.images[0].get_pixel(x, y), BADGE);
+        assert_ne!(*core.images[0].get_pixel(x, y), BADGE);
+
+        let parts = Question::compose(&screen.image, &page, ImageLayout::Parts, false);
+        assert_eq!(parts.images.len(), 5);
+        assert_eq!(
+            parts.images[2].dimensions(),
+            (option.width(), option.height())
+        );
+        let labeled = Question::compose(&screen.image, &page, ImageLayout::Parts, true);
+        let x = option.width() - 1 - pad - badge;
+        assert_eq!(*labeled.images[2].get_pixel(x, pad), BADGE);
+        assert_ne!(*parts.images[2].get_pixel(x, pad), BADGE);
+
+        // 标注在选项的左侧
+        let stitch = Question::compose(&screen.image, &page, ImageLayout::Stitch, false);
+        assert!(stitch.labeled);
+        assert_eq!(stitch.images.len(), 1);
+        assert!(stitch.images[0].height() < core.images[0].height());
+        let heights = page.check_boxes.iter().map(|x| x.height());
+        let badge = badge_size(heights.clone().min().unwrap());
+        let mut top = stem(&page).height() + GAP;
+        for (i, height) in heights.enumerate() {
+            let y = top + (height - badge) / 2;
+            assert_eq!(*stitch.images[0].get_pixel(GAP, y), BADGE, "{i}");
+            assert_eq!(*stitch.images[0].get_pixel(GAP - 1, y), WHITE, "{i}");
+            top += height + GAP;
+        }
+    }
+
+    #[test]
+    fn test_small_option() {
+        // 选项比标注的最小边长还矮时不能溢出
+        let option = RgbImage::from_pixel(200, 4, WHITE);
+        let mut labeled = option.clone();
+        label_option(&mut labeled, &Rect::at(0, 0).of_size(200, 4), Answer::A);
+        assert_eq!(labeled, option);
+        let stem = RgbImage::from_pixel(200, 20, WHITE);
+        let img = stitch(&stem, &[option.clone(), option]);
+        assert_eq!(img.height(), 20 + (4 + GAP) * 2);
+    }
+}
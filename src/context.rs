@@ -1,8 +1,14 @@
 use std::path::PathBuf;
 
-use crate::logging::LogFormat;
+use crate::{
+    answerer::{AnswererKind, ReasoningEffort, ReasoningStyle, UploadFormat, Vote},
+    compose::ImageLayout,
+    logging::LogFormat,
+    mock_llm::MockStep,
+    vision::DEFAULT_BAND,
+};
 
-#[derive(clap::Parser, Debug)]
+#[derive(clap::Parser, Clone, Debug)]
 pub struct Context {
     #[arg(long, default_value_t = log::Level::Info, env = "BILI_LV6_HARDCORE_LOG_LEVEL")]
     pub log_level: log::Level,
@@ -39,13 +45,177 @@ pub struct Context {
         env = "BILI_LV6_HARDCORE_ANSWER_THINKING"
     )]
     pub answer_thinking: bool,
+    /// 开启思考时思考可以使用的 token 数，仅 Anthropic、Gemini 以及 --answer-reasoning enable-thinking 使用
+    #[arg(
+        long,
+        default_value_t = 2048,
+        env = "BILI_LV6_HARDCORE_ANSWER_THINKING_BUDGET"
+    )]
+    pub answer_thinking_budget: u32,
+    /// OpenAI 兼容接口中控制思考的参数，不同厂商使用的参数不同
+    #[arg(
+        long,
+        default_value = "thinking",
+        env = "BILI_LV6_HARDCORE_ANSWER_REASONING"
+    )]
+    pub answer_reasoning: ReasoningStyle,
+    /// --answer-reasoning 为 effort 并且开启思考时的 reasoning_effort
+    #[arg(
+        long,
+        default_value = "medium",
+        env = "BILI_LV6_HARDCORE_ANSWER_REASONING_EFFORT"
+    )]
+    pub answer_reasoning_effort: ReasoningEffort,
+
+    /// 发送给模型的图片的组织方式
+    #[arg(
+        long,
+        default_value = "core",
+        env = "BILI_LV6_HARDCORE_ANSWER_IMAGE_LAYOUT"
+    )]
+    pub answer_image_layout: ImageLayout,
+    /// 在发送给模型的图片中的每个选项上标注选项字母
+    #[arg(
+        long,
+        default_value_t = false,
+        env = "BILI_LV6_HARDCORE_ANSWER_LABEL_OPTIONS"
+    )]
+    pub answer_label_options: bool,
+    /// 要求模型输出包含答案和置信度的 JSON，解析失败时仍然按文字解析
+    #[arg(
+        long,
+        default_value_t = false,
+        env = "BILI_LV6_HARDCORE_ANSWER_STRUCTURED"
+    )]
+    pub answer_structured: bool,
+    /// 请求答案 token 的 logprobs，换算成每个选项的概率，仅 OpenAI 兼容接口和 llama.cpp 使用
+    #[arg(
+        long,
+        default_value_t = false,
+        env = "BILI_LV6_HARDCORE_ANSWER_LOGPROBS"
+    )]
+    pub answer_logprobs: bool,
+    /// 自我检查模式，先让模型分析并回答，再追加一轮对话让模型对照图片检查答案，只输出最终的选项字母
+    #[arg(long, default_value_t = false, env = "BILI_LV6_HARDCORE_ANSWER_VERIFY")]
+    pub answer_verify: bool,
+    /// 使用流式回答，第一行只有选项字母或者出现 `答案：X` 时不等待回答结束，仅 OpenAI 兼容接口使用
+    #[arg(long, default_value_t = false, env = "BILI_LV6_HARDCORE_ANSWER_STREAM")]
+    pub answer_stream: bool,
+    /// 答案的概率低于该值时交给更强的模型重新回答
+    #[arg(long, env = "BILI_LV6_HARDCORE_ANSWER_ESCALATE_BELOW")]
+    pub answer_escalate_below: Option<f32>,
+    /// 每题回答两次，两次答案不一致时交给更强的模型重新回答
+    #[arg(
+        long,
+        default_value_t = false,
+        env = "BILI_LV6_HARDCORE_ANSWER_ESCALATE_ON_DISAGREE"
+    )]
+    pub answer_escalate_on_disagree: bool,
+    /// 重新回答时使用的模型，默认和 --api-model 相同，重新回答时总是开启思考
+    #[arg(long, env = "BILI_LV6_HARDCORE_ANSWER_ESCALATE_MODEL")]
+    pub answer_escalate_model: Option<String>,
+
+    /// 提示词模板所在的目录，按 --question-category 选择 `<分类>.txt`，不存在时使用 `default.txt`
+    #[arg(long, env = "BILI_LV6_HARDCORE_PROMPT_DIR")]
+    pub prompt_dir: Option<PathBuf>,
+    /// 题目的分类，例如选择答题分类时选择的“历史”，用于选择提示词模板，也是模板中的 category 变量
+    #[arg(long, env = "BILI_LV6_HARDCORE_QUESTION_CATEGORY")]
+    pub question_category: Option<String>,
+    /// 少样本示例所在的目录，文件名为 `<名字>.<答案>.<png|jpg|txt>`，例如 `history-1.B.png`，
+    /// 示例作为之前的问答放在每次请求的最前面
+    #[arg(long, env = "BILI_LV6_HARDCORE_FEW_SHOT_DIR")]
+    pub few_shot_dir: Option<PathBuf>,
+    /// 每次请求最多使用的少样本示例的数量，按文件名排序选择
+    #[arg(long, default_value = "2", env = "BILI_LV6_HARDCORE_FEW_SHOT_COUNT")]
+    pub few_shot_count: usize,
+
+    /// 回答题目使用的后端
+    #[arg(long, default_value = "openai", env = "BILI_LV6_HARDCORE_ANSWERER")]
+    pub answerer: AnswererKind,
+    /// 同时询问多个模型并投票，JSON 格式的模型列表，每个模型没有设置的字段使用命令行参数的值
+    #[arg(long, env = "BILI_LV6_HARDCORE_ENSEMBLE")]
+    pub ensemble: Option<PathBuf>,
+    /// 合并多个模型的回答的方式
+    #[arg(
+        long,
+        default_value = "majority",
+        env = "BILI_LV6_HARDCORE_ENSEMBLE_VOTE"
+    )]
+    pub ensemble_vote: Vote,
 
     #[arg(long, env = "BILI_LV6_HARDCORE_API_URL")]
     pub api_url: String,
     #[arg(long, env = "BILI_LV6_HARDCORE_API_MODEL")]
     pub api_model: String,
-    #[arg(long, env = "BILI_LV6_HARDCORE_API_KEY")]
+    /// 本地模型可以不设置
+    #[arg(
+        long,
+        default_value = "",
+        hide_default_value = true,
+        env = "BILI_LV6_HARDCORE_API_KEY"
+    )]
     pub api_key: String,
+    /// 请求的超时时间，单位秒，本地使用 CPU 推理时需要适当调大
+    #[arg(long, default_value_t = 600, env = "BILI_LV6_HARDCORE_API_TIMEOUT")]
+    pub api_timeout: u64,
+    /// 请求超时、连接失败、被限流或者服务出错时重试的总时间，单位秒，超过后本题放弃使用模型的回答
+    #[arg(
+        long,
+        default_value_t = 120,
+        env = "BILI_LV6_HARDCORE_API_RETRY_BUDGET"
+    )]
+    pub api_retry_budget: u64,
+    /// 发送给模型之前把图片等比例缩小到长边不超过这个像素数，默认不缩小
+    #[arg(long, env = "BILI_LV6_HARDCORE_IMAGE_MAX_EDGE")]
+    pub image_max_edge: Option<u32>,
+    /// 发送灰度图片，可以减小 JPEG 的体积
+    #[arg(
+        long,
+        default_value_t = false,
+        env = "BILI_LV6_HARDCORE_IMAGE_GRAYSCALE"
+    )]
+    pub image_grayscale: bool,
+    /// 调整图片的对比度，单位是百分比，正数增强，负数减弱，0 为不调整
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        allow_negative_numbers = true,
+        env = "BILI_LV6_HARDCORE_IMAGE_CONTRAST"
+    )]
+    pub image_contrast: f32,
+    /// 图片的编码格式
+    #[arg(long, default_value = "jpeg", env = "BILI_LV6_HARDCORE_IMAGE_FORMAT")]
+    pub image_format: UploadFormat,
+    /// JPEG 的质量，1 到 100
+    #[arg(long, default_value_t = 75, env = "BILI_LV6_HARDCORE_IMAGE_QUALITY")]
+    pub image_quality: u8,
+    /// 把模型接口的请求和响应追加到这个文件，不包括 API KEY，图片替换为哈希，用于之后离线重放
+    #[arg(long, env = "BILI_LV6_HARDCORE_API_RECORD")]
+    pub api_record: Option<PathBuf>,
+    /// 不请求模型接口，从 --api-record 记录的文件中按模型和图片重放响应
+    #[arg(long, env = "BILI_LV6_HARDCORE_API_REPLAY")]
+    pub api_replay: Option<PathBuf>,
+    /// 调试用，启动模拟的 OpenAI 兼容接口代替 --api-url，逗号分隔的响应按顺序循环使用，
+    /// 每个响应为 `<A-D|random|garbage|429|500>[@<毫秒>]`，例如 `429,garbage,B@500`
+    #[arg(long, value_delimiter = ',', env = "BILI_LV6_HARDCORE_MOCK_LLM")]
+    pub mock_llm: Vec<MockStep>,
+    /// 回答可以使用的最大 token 数，仅 Anthropic 使用
+    #[arg(long, default_value_t = 1024, env = "BILI_LV6_HARDCORE_API_MAX_TOKENS")]
+    pub api_max_tokens: u32,
+    /// Anthropic API 的 anthropic-version 请求头
+    #[arg(
+        long,
+        default_value = "2023-06-01",
+        env = "BILI_LV6_HARDCORE_ANTHROPIC_VERSION"
+    )]
+    pub anthropic_version: String,
+    /// Gemini 通过 URL 中的 key 参数而不是 x-goog-api-key 请求头传递 API KEY
+    #[arg(
+        long,
+        default_value_t = false,
+        env = "BILI_LV6_HARDCORE_GEMINI_KEY_IN_QUERY"
+    )]
+    pub gemini_key_in_query: bool,
 
     /// 每百万输入token的费用
     #[arg(
@@ -62,6 +232,58 @@ pub struct Context {
     )]
     pub api_cost_output: f64,
 
+    /// 画面保持静止多久才认为页面已经就绪，单位毫秒
+    #[arg(
+        long,
+        default_value_t = 300,
+        env = "BILI_LV6_HARDCORE_SCREEN_STABLE_MS"
+    )]
+    pub screen_stable_ms: u64,
+    /// 相邻两帧缩略图平均每个像素的差值小于该值时认为画面静止
+    #[arg(
+        long,
+        default_value_t = 2.0,
+        env = "BILI_LV6_HARDCORE_SCREEN_DIFF_THRESHOLD"
+    )]
+    pub screen_diff_threshold: f32,
+
+    /// 识别页面时先将截图缩小的倍数，1 表示直接使用原始分辨率
+    #[arg(long, default_value_t = 2, env = "BILI_LV6_HARDCORE_DETECT_SCALE")]
+    pub detect_scale: u32,
+    /// 题目和选项所在的纵向范围，按截图高度的比例，格式为 `上,下`，只在这个范围内识别页面
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_values_t = [DEFAULT_BAND.0, DEFAULT_BAND.1],
+        env = "BILI_LV6_HARDCORE_DETECT_BAND"
+    )]
+    pub detect_band: Vec<f32>,
+
+    /// 题库文件，JSON Lines 格式，回答过的题目再次遇到时直接使用记录的答案
+    #[arg(long, env = "BILI_LV6_HARDCORE_QUESTION_BANK")]
+    pub question_bank: Option<PathBuf>,
+    /// 题库中认为是同一张图片的最大汉明距离，哈希共 256 位
+    #[arg(
+        long,
+        default_value_t = 16,
+        env = "BILI_LV6_HARDCORE_QUESTION_BANK_DISTANCE"
+    )]
+    pub question_bank_distance: u32,
+
+    /// 社区整理的题目列表，CSV 或者 JSON 格式，可以用逗号分隔多个文件，有题目的文字时按文字查找答案
+    #[arg(long, value_delimiter = ',', env = "BILI_LV6_HARDCORE_COMMUNITY_LIST")]
+    pub community_list: Vec<PathBuf>,
+    /// 按文字查找题目列表时题干和答案的相似度下限
+    #[arg(
+        long,
+        default_value_t = 0.8,
+        env = "BILI_LV6_HARDCORE_COMMUNITY_SIMILARITY"
+    )]
+    pub community_similarity: f32,
+    /// 将本次模型转写的题目和答案导出到该文件，格式和 --community-list 相同
+    #[arg(long, env = "BILI_LV6_HARDCORE_COMMUNITY_EXPORT")]
+    pub community_export: Option<PathBuf>,
+
     /// 调试用，保存未识别的截图
     #[arg(long, env = "BILI_LV6_HARDCORE_DEBUG_SAVE_PATH")]
     pub debug_save_path: Option<PathBuf>,
@@ -70,5 +292,67 @@ pub struct Context {
 impl Context {
     pub fn check(&mut self) {
         assert!(self.answer_fallback_ratio >= 0.0 && self.answer_fallback_ratio <= 1.0);
+        assert!(self.screen_diff_threshold > 0.0);
+        assert!(self.detect_scale > 0);
+        assert!(
+            self.detect_band.len() == 2
+                && 0.0 <= self.detect_band[0]
+                && self.detect_band[0] < self.detect_band[1]
+                && self.detect_band[1] <= 1.0,
+            "detect_band must be two ratios: 0 <= top < bottom <= 1"
+        );
+        assert!(
+            !self.api_key.is_empty()
+                || self.answerer.is_local()
+                || self.ensemble.is_some()
+                || self.api_replay.is_some()
+                || !self.mock_llm.is_empty(),
+            "--api-key is required for {:?}",
+            self.answerer
+        );
+        if let Some(threshold) = self.answer_escalate_below {
+            assert!(
+                threshold > 0.0 && threshold <= 1.0,
+                "answer_escalate_below must be in (0, 1]"
+            );
+            assert!(
+                self.answer_structured || self.answer_logprobs,
+                "--answer-escalate-below requires --answer-structured or --answer-logprobs to get the answer probability"
+            );
+        }
+        assert!(
+            self.community_similarity > 0.0 && self.community_similarity <= 1.0,
+            "community_similarity must be in (0, 1]"
+        );
+        assert!(
+            self.answer_structured
+                || (self.community_list.is_empty() && self.community_export.is_none()),
+            "--community-list and --community-export require --answer-structured to transcribe the question"
+        );
+        assert!(
+            !(self.answer_verify && self.answer_structured),
+            "--answer-verify requires free-form answers and cannot be used with --answer-structured"
+        );
+        assert!(
+            self.mock_llm.is_empty()
+                || (matches!(self.answerer, AnswererKind::OpenAi) && self.ensemble.is_none()),
+            "--mock-llm only works with --answerer openai and without --ensemble"
+        );
+        assert!(
+            (1..=100).contains(&self.image_quality),
+            "image quality must be in [1, 100]"
+        );
+        assert!(
+            self.image_max_edge != Some(0),
+            "image max edge must be positive"
+        );
+        assert!(
+            self.api_record.is_none() || self.api_replay.is_none(),
+            "--api-record and --api-replay cannot be used together"
+        );
+        assert!(
+            self.answer_thinking_budget >= 1024,
+            "thinking budget must be at least 1024 tokens"
+        );
     }
 }
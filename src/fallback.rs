@@ -0,0 +1,137 @@
+//! 模型没有给出有效答案时重新回答，仍然失败时随机选择
+
+use crate::{
+    answerer::{Answer, Answerer, Reply},
+    compose::Question,
+    context::Context,
+};
+
+pub struct Fallback {
+    /// 每道题重新回答的次数
+    retry_limit: u32,
+    /// 随机选择的题数占所有题数的比例上限，超过时退出
+    ratio_limit: f32,
+    /// 随机选择的题数
+    count: u32,
+}
+
+impl Fallback {
+    pub fn from_args(ctx: &Context) -> Self {
+        Self::new(ctx.answer_retry_limit, ctx.answer_fallback_ratio)
+    }
+
+    pub fn new(retry_limit: u32, ratio_limit: f32) -> Self {
+        Self {
+            retry_limit,
+            ratio_limit,
+            count: 0,
+        }
+    }
+
+    /// 返回回答以及是否是随机选择的，`question_count` 是包括这道题在内的题数
+    pub fn answer(
+        &mut self,
+        answerer: &mut dyn Answerer,
+        question: &Question,
+        question_count: u32,
+    ) -> (Reply, bool) {
+        let mut retry_count = 0u32;
+        loop {
+            if let Some(reply) = answerer.answer(question) {
+                return (reply, false);
+            }
+            if retry_count < self.retry_limit {
+                retry_count += 1;
+                log::info!(
+                    "Failed to get a valid answer, retrying... ({} / {})",
+                    retry_count,
+                    self.retry_limit
+                );
+                continue;
+            }
+            self.count += 1;
+            let limit = self.ratio_limit;
+            let ratio = self.count as f32 / question_count as f32;
+            assert!(
+                ratio <= limit,
+                "Fallback ratio exceeded: {ratio:.3} > {limit:.3}",
+            );
+            let ans = Answer::random();
+            log::warn!(
+                "Failed to parse answer, use random answer: {}, fallback count: {}/{}({:.3})",
+                ans.to_str(),
+                self.count,
+                question_count,
+                ratio
+            );
+            return (Reply::from(ans), true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        answerer, logging,
+        mock_llm::{MockLlm, MockStep},
+    };
+    use clap::Parser;
+
+    use super::*;
+
+    /// 使用模拟服务的 OpenAI 兼容后端
+    fn answerer(server: &MockLlm, args: &[&str]) -> Box<dyn Answerer> {
+        let url = server.url();
+        let mut argv = vec![
+            "test",
+            "--api-url",
+            &url,
+            "--api-model",
+            "mock-model",
+            "--api-key",
+            "sk-mock",
+        ];
+        argv.extend(args);
+        answerer::from_args(&Context::parse_from(argv))
+    }
+
+    fn steps(spec: &str) -> Vec<MockStep> {
+        spec.split(',').map(|x| x.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_retry() {
+        logging::init_for_test();
+        // 429 由请求重试，无法解析的回答由 Fallback 重新回答
+        let server = MockLlm::start(steps("429,garbage,B"));
+        let mut answerer = answerer(&server, &[]);
+        let mut fallback = Fallback::new(1, 0.0);
+        let (reply, random) = fallback.answer(answerer.as_mut(), &Question::for_test(), 1);
+        assert_eq!((reply.answer, random), (Answer::B, false));
+        assert_eq!(server.requests(), 3);
+        assert_eq!(fallback.count, 0);
+        assert!(answerer.usage().input_tokens > 0);
+    }
+
+    #[test]
+    fn test_fallback() {
+        logging::init_for_test();
+        let server = MockLlm::start(steps("garbage"));
+        let mut answerer = answerer(&server, &["--answer-stream"]);
+        let mut fallback = Fallback::new(2, 0.5);
+        let (_, random) = fallback.answer(answerer.as_mut(), &Question::for_test(), 2);
+        assert!(random);
+        assert_eq!(server.requests(), 3);
+        assert_eq!(fallback.count, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Fallback ratio exceeded")]
+    fn test_fallback_ratio() {
+        logging::init_for_test();
+        let server = MockLlm::start(steps("500,garbage"));
+        let mut answerer = answerer(&server, &["--api-retry-budget", "0"]);
+        let mut fallback = Fallback::new(1, 0.5);
+        fallback.answer(answerer.as_mut(), &Question::for_test(), 1);
+    }
+}
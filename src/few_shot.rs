@@ -0,0 +1,122 @@
+//! 少样本示例，作为之前的对话放在请求的最前面
+//!
+//! 示例目录中的每个文件是一个示例，文件名的格式是 `<名字>.<答案>.<扩展名>`，
+//! 例如 `history-1.B.png`、`anime-2.D.txt`。图片应该和发送给模型的题目图片的格式相同，
+//! 文字示例需要包含题干和选项。示例按文件名排序，只使用前 `count` 个。
+
+use std::path::Path;
+
+use image::RgbImage;
+
+use crate::{answerer::Answer, context::Context};
+
+#[derive(Clone, Debug)]
+pub struct Example {
+    pub name: String,
+    pub content: ExampleContent,
+    pub answer: Answer,
+}
+
+#[derive(Clone, Debug)]
+pub enum ExampleContent {
+    Image(RgbImage),
+    Text(String),
+}
+
+pub fn from_args(ctx: &Context) -> Vec<Example> {
+    match &ctx.few_shot_dir {
+        Some(dir) if ctx.few_shot_count > 0 => load(dir, ctx.few_shot_count),
+        _ => vec![],
+    }
+}
+
+pub fn load(dir: &Path, count: usize) -> Vec<Example> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", dir.display()))
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.is_file())
+        .collect();
+    paths.sort();
+    let examples: Vec<_> = paths
+        .iter()
+        .filter_map(|path| {
+            let example = load_example(path);
+            if example.is_none() {
+                log::warn!("Skip few-shot example {}", path.display());
+            }
+            example
+        })
+        .take(count)
+        .collect();
+    log::info!(
+        "few-shot examples: {}",
+        examples
+            .iter()
+            .map(|x| format!("{}: {}", x.name, x.answer.to_str()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    examples
+}
+
+fn load_example(path: &Path) -> Option<Example> {
+    let (name, answer) = parse_name(path)?;
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let content = match extension.as_str() {
+        "txt" => ExampleContent::Text(std::fs::read_to_string(path).ok()?.trim().to_owned()),
+        "png" | "jpg" | "jpeg" => ExampleContent::Image(image::open(path).ok()?.to_rgb8()),
+        _ => return None,
+    };
+    Some(Example {
+        name,
+        content,
+        answer,
+    })
+}
+
+/// `history-1.B.png` 的名字是 `history-1`，答案是 B
+fn parse_name(path: &Path) -> Option<(String, Answer)> {
+    let stem = path.file_stem()?.to_str()?;
+    let (name, letter) = stem.rsplit_once('.')?;
+    let answer = match letter.to_ascii_uppercase().as_str() {
+        "A" => Answer::A,
+        "B" => Answer::B,
+        "C" => Answer::C,
+        "D" => Answer::D,
+        _ => return None,
+    };
+    Some((name.to_owned(), answer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("few-shot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("a-text.C.txt"),
+            "1 + 2 = ?\nA. 1\nB. 2\nC. 3\nD. 4\n",
+        )
+        .unwrap();
+        RgbImage::new(16, 8)
+            .save(dir.join("b-image.b.png"))
+            .unwrap();
+        std::fs::write(dir.join("c-no-answer.txt"), "").unwrap();
+        std::fs::write(dir.join("d-last.A.txt"), "").unwrap();
+
+        let examples = load(&dir, 2);
+        assert_eq!(examples.len(), 2);
+        assert_eq!(examples[0].name, "a-text");
+        assert!(matches!(examples[0].answer, Answer::C));
+        assert!(matches!(&examples[0].content, ExampleContent::Text(x) if x.ends_with("D. 4")));
+        assert_eq!(examples[1].name, "b-image");
+        assert!(matches!(examples[1].answer, Answer::B));
+        assert!(matches!(&examples[1].content, ExampleContent::Image(x) if x.width() == 16));
+
+        assert_eq!(load(&dir, 10).len(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+//! 测试用的合成答题页面截图
+
+use image::{Rgba, RgbaImage};
+use imageproc::{
+    drawing::{draw_filled_rect_mut, draw_hollow_rect_mut},
+    rect::Rect,
+};
+
+const BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const CARD: Rgba<u8> = Rgba([245, 245, 245, 255]);
+const TEXT: Rgba<u8> = Rgba([40, 40, 40, 255]);
+const BORDER: Rgba<u8> = Rgba([170, 170, 170, 255]);
+
+/// 合成的答题页面以及其中各个元素的位置
+pub(crate) struct Screen {
+    pub(crate) image: RgbaImage,
+    /// 标题以及题号等题目上方的内容
+    pub(crate) header: Rect,
+    /// 题干所在的卡片
+    pub(crate) card: Rect,
+    /// 题干文字所占的区域，没有题干时为 `None`
+    pub(crate) stem: Option<Rect>,
+    pub(crate) options: Vec<Rect>,
+}
+
+/// 生成 1080x2400 的答题页面，题干有 `lines` 行文字，`lines` 为 0 时只有空白的卡片
+pub(crate) fn question_screen(lines: usize) -> Screen {
+    let options = [4, 7, 10, 13].map(|x| "x".repeat(x));
+    question_screen_with_options(lines, &options.each_ref().map(String::as_str))
+}
+
+/// 与 [`question_screen`] 相同，每个选项的文字中空格以外的字符画成一个方块，空格留空
+pub(crate) fn question_screen_with_options(lines: usize, option_texts: &[&str; 4]) -> Screen {
+    const WIDTH: u32 = 1080;
+    const HEIGHT: u32 = 2400;
+    const MARGIN: i32 = 60;
+    let mut image = RgbaImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+
+    let title = draw_text_line(&mut image, MARGIN, 120, 8, 48);
+    let progress = draw_text_line(&mut image, MARGIN, 260, 5, 40);
+    let header = union(&title, &progress);
+
+    const LINE_HEIGHT: i32 = 72;
+    const PADDING: i32 = 48;
+    let card_top = 400;
+    let card_height = PADDING * 2 + LINE_HEIGHT * lines as i32;
+    let card = Rect::at(MARGIN / 2, card_top).of_size(WIDTH - MARGIN as u32, card_height as u32);
+    draw_filled_rect_mut(&mut image, card, CARD);
+    let mut stem: Option<Rect> = None;
+    for i in 0..lines {
+        let glyphs = if i + 1 == lines { 6 + i * 2 } else { 18 };
+        let y = card_top + PADDING + LINE_HEIGHT * i as i32 + 14;
+        let line = draw_text_line(&mut image, MARGIN + 10, y, glyphs, 44);
+        stem = Some(stem.map_or(line, |x| union(&x, &line)));
+    }
+
+    const OPTION_HEIGHT: u32 = 150;
+    const OPTION_GAP: i32 = 40;
+    let mut options = vec![];
+    let mut y = card.bottom() + 80;
+    for text in option_texts {
+        let rect = Rect::at(MARGIN, y).of_size(WIDTH - MARGIN as u32 * 2, OPTION_HEIGHT);
+        for t in 0..3 {
+            let border = Rect::at(rect.left() + t, rect.top() + t)
+                .of_size(rect.width() - t as u32 * 2, rect.height() - t as u32 * 2);
+            draw_hollow_rect_mut(&mut image, border, BORDER);
+        }
+        draw_glyphs(&mut image, rect.left() + 40, rect.top() + 55, text, 40);
+        options.push(rect);
+        y += OPTION_HEIGHT as i32 + OPTION_GAP;
+    }
+
+    Screen {
+        image,
+        header,
+        card,
+        stem,
+        options,
+    }
+}
+
+/// 用方块模拟一行文字
+fn draw_text_line(image: &mut RgbaImage, left: i32, top: i32, glyphs: usize, size: u32) -> Rect {
+    let step = size as i32 + size as i32 / 6;
+    for i in 0..glyphs {
+        let glyph = Rect::at(left + step * i as i32, top).of_size(size, size);
+        draw_filled_rect_mut(image, glyph, TEXT);
+    }
+    Rect::at(left, top).of_size(step as u32 * glyphs as u32 - size / 6, size)
+}
+
+fn draw_glyphs(image: &mut RgbaImage, left: i32, top: i32, text: &str, size: u32) {
+    let step = size as i32 + size as i32 / 6;
+    for (i, c) in text.chars().enumerate() {
+        if c != ' ' {
+            let glyph = Rect::at(left + step * i as i32, top).of_size(size, size);
+            draw_filled_rect_mut(image, glyph, TEXT);
+        }
+    }
+}
+
+fn union(a: &Rect, b: &Rect) -> Rect {
+    let left = a.left().min(b.left());
+    let top = a.top().min(b.top());
+    let right = a.right().max(b.right());
+    let bottom = a.bottom().max(b.bottom());
+    Rect::at(left, top).of_size((right - left + 1) as u32, (bottom - top + 1) as u32)
+}
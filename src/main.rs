@@ -1,24 +1,43 @@
 // #![deny(clippy::unwrap_used)]
 mod adb;
 mod answerer;
+mod bank;
+mod community;
+mod compose;
 mod context;
+mod fallback;
+mod few_shot;
+#[cfg(test)]
+mod fixtures;
 mod logging;
+mod mock_llm;
 mod page;
-mod utils;
+mod prompt;
+mod stability;
+mod vision;
 
 use std::{
     path::{Path, PathBuf},
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use clap::Parser;
-use image::{GenericImageView, GrayImage, RgbaImage, buffer::ConvertBuffer};
+use image::RgbaImage;
 use imageproc::drawing::draw_hollow_rect_mut;
 
 use adb::Adb;
-use answerer::{Answer, Multimodal};
+use answerer::Calibration;
+use bank::{Bank, Fingerprint};
+use community::{QuestionList, Record};
+use compose::Question;
 use context::Context;
+use fallback::Fallback;
+use mock_llm::MockLlm;
 use page::PageQuestion;
+use prompt::Template;
+use stability::FrameStability;
+use vision::Pipeline;
 
 fn main() {
     let ctx = global_init();
@@ -27,21 +46,25 @@ fn main() {
     if let Some(device) = &ctx.device {
         adb.set_device(device.to_owned());
     }
-    wait_question_page(&adb);
+    let mut stability = FrameStability::from_args(&ctx);
+    let pipeline = Pipeline::from_args(&ctx);
+    wait_question_page(&adb, &pipeline, &mut stability);
 
-    let mut answerer = Multimodal::from_args(&ctx);
+    let mut answerer = answerer::from_args(&ctx);
+    let template = Template::from_args(&ctx);
+    let examples: Arc<[_]> = few_shot::from_args(&ctx).into();
+    let mut calibration = Calibration::default();
+    let mut bank = Bank::from_args(&ctx);
+    let list = QuestionList::from_args(&ctx);
+    let mut fallback = Fallback::from_args(&ctx);
     let mut question_count = 0u32;
-    let mut fallback_count = 0u32;
-    const INTERVAL: Duration = Duration::from_millis(750);
     loop {
         let mut res = None;
         const IDENTIFY_TRY_LIMIT: usize = 2;
-        for i in 0..IDENTIFY_TRY_LIMIT {
-            if i != 0 {
-                std::thread::sleep(INTERVAL);
-            }
-            if let Some(val) = identify_screen(&adb, &ctx.debug_save_path) {
-                res = Some(val);
+        for _ in 0..IDENTIFY_TRY_LIMIT {
+            let screen = wait_stable_screen(&adb, &mut stability);
+            if let Some(val) = identify_screen(&pipeline, &screen, &ctx.debug_save_path) {
+                res = Some((screen, val));
                 break;
             }
         }
@@ -49,62 +72,114 @@ fn main() {
             log::info!("No question page detected");
             break;
         }
-        let (question, page) = res.unwrap();
+        let (screen, page) = res.unwrap();
+        let mut question = Question::compose(
+            &screen,
+            &page,
+            ctx.answer_image_layout,
+            ctx.answer_label_options,
+        );
+        question.transcribe = list.is_some() || ctx.community_export.is_some();
+        question.verify = ctx.answer_verify;
+        question.prompt = template.render(&question, ctx.answer_structured);
+        question.examples = examples.clone();
 
         question_count += 1;
 
-        let mut retry_count = 0u32;
-        let ans = loop {
-            let ans = answerer.answer(&question);
-            if let Some(ans) = ans {
-                break ans;
+        let mut fingerprint = bank
+            .as_ref()
+            .map(|_| Fingerprint::new(&screen, &page, question.text.as_ref()));
+        let banked = match (&mut bank, &fingerprint) {
+            (Some(bank), Some(fingerprint)) => bank.lookup(fingerprint),
+            _ => None,
+        };
+        // 题库中有之前转写的文字时，不询问模型也可以在社区列表中查找
+        let listed = match (&list, &bank, &fingerprint) {
+            (Some(list), Some(bank), Some(fingerprint)) if banked.is_none() => bank
+                .transcript(fingerprint)
+                .and_then(|text| list.lookup(&text)),
+            _ => None,
+        };
+        let ans = if let Some(ans) = banked {
+            log::info!("Answer from question bank: {}", ans.to_str());
+            ans
+        } else if let Some(ans) = listed {
+            log::info!("Answer from community list: {}", ans.to_str());
+            ans
+        } else {
+            let (reply, random) = fallback.answer(answerer.as_mut(), &question, question_count);
+            calibration.record(&reply);
+            if let Some(probability) = reply.probability() {
+                log::debug!("probability: {probability:.3}");
             }
-            if retry_count < ctx.answer_retry_limit {
-                retry_count += 1;
-                log::info!(
-                    "Failed to get a valid answer, retrying... ({} / {})",
-                    retry_count,
-                    ctx.answer_retry_limit
-                );
-                continue;
+            let mut ans = reply.answer;
+            if let Some(text) = &reply.text {
+                log::debug!("transcribed question: {text:?}");
+                if let Some(listed) = list.as_ref().and_then(|x| x.lookup(text))
+                    && listed != ans
+                {
+                    log::info!(
+                        "Community list answer {} overrides model answer {}",
+                        listed.to_str(),
+                        ans.to_str()
+                    );
+                    ans = listed;
+                }
+                if let Some(fingerprint) = &mut fingerprint {
+                    fingerprint.set_text(text);
+                }
+                if let Some(path) = &ctx.community_export {
+                    community::export(path, &[Record::new(text, ans)]);
+                }
+            }
+            if !random && let (Some(bank), Some(fingerprint)) = (&mut bank, &fingerprint) {
+                bank.record(fingerprint, ans);
             }
-            fallback_count += 1;
-            let limit = ctx.answer_fallback_ratio;
-            let ratio = fallback_count as f32 / question_count as f32;
-            assert!(
-                ratio <= limit,
-                "Fallback ratio exceeded: {ratio:.3} > {limit:.3}",
-            );
-            let ans = Answer::random();
-            log::warn!(
-                "Failed to parse answer, use random answer: {}, fallback count: {}/{}({:.3})",
-                ans.to_str(),
-                fallback_count,
-                question_count,
-                ratio
-            );
-            break ans;
+            ans
         };
         let choice = page.choice(ans);
-        log::info!("{:03}: answer: {:?}, tap screen", question_count, ans);
+        log::info!(
+            "{:03}: answer: {:?}, prompt: {}, tap screen",
+            question_count,
+            ans,
+            question.prompt.version
+        );
         adb.tap_random(choice);
-        std::thread::sleep(INTERVAL);
+        wait_screen_change(&adb, &stability, &screen);
+    }
+    calibration.log_summary();
+    answerer.log_summary();
+    if let Some(bank) = &bank {
+        bank.log_summary();
     }
+    for (name, usage) in answerer.usage_details() {
+        log::info!(
+            "cost: {}: tokens: input: {}, output: {}, reasoning: {}, total: {}, {:.3}RMB",
+            name,
+            usage.input_tokens,
+            usage.output_tokens,
+            usage.reasoning_tokens,
+            usage.tokens(),
+            usage.cost
+        );
+    }
+    let usage = answerer.usage();
     log::info!(
-        "cost: question: {}: tokens: input: {}, output: {}, total: {}, {:.3}RMB",
+        "cost: question: {}: tokens: input: {}, output: {}, reasoning: {}, total: {}, {:.3}RMB",
         question_count,
-        answerer.input_tokens(),
-        answerer.output_tokens(),
-        answerer.tokens(),
-        answerer.input_tokens() as f64 / 1_000_000f64 * ctx.api_cost_input
-            + answerer.output_tokens() as f64 / 1_000_000f64 * ctx.api_cost_output
+        usage.input_tokens,
+        usage.output_tokens,
+        usage.reasoning_tokens,
+        usage.tokens(),
+        usage.cost
     );
 }
 
-fn wait_question_page(adb: &Adb) {
+fn wait_question_page(adb: &Adb, pipeline: &Pipeline, stability: &mut FrameStability) {
     loop {
         log::info!("Waiting for question page...");
-        if identify_screen(adb, &None).is_some() {
+        let screen = wait_stable_screen(adb, stability);
+        if identify_screen(pipeline, &screen, &None).is_some() {
             log::info!("Question page detected");
             return;
         }
@@ -112,22 +187,51 @@ fn wait_question_page(adb: &Adb) {
     }
 }
 
-fn identify_screen(adb: &Adb, save_error: &Option<PathBuf>) -> Option<(RgbaImage, PageQuestion)> {
-    let screen = adb.screencap();
-    let mut edges = edge_detection(&screen);
-    match PageQuestion::match_page(&edges) {
-        Ok(question) => {
-            let core = &question.core;
-            let core = screen
-                .view(
-                    core.left() as u32,
-                    core.top() as u32,
-                    core.width(),
-                    core.height(),
-                )
-                .to_image();
-            Some((core, question))
+/// 持续截图直到画面静止，超时则返回最后一帧
+fn wait_stable_screen(adb: &Adb, stability: &mut FrameStability) -> RgbaImage {
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const POLL: Duration = Duration::from_millis(50);
+    let start = Instant::now();
+    stability.reset();
+    loop {
+        let screen = adb.screencap();
+        if stability.update(&screen) {
+            log::debug!("screen stable after {:?}", start.elapsed());
+            return screen;
+        }
+        if start.elapsed() >= TIMEOUT {
+            log::warn!("screen is not stable after {:?}", TIMEOUT);
+            return screen;
         }
+        std::thread::sleep(POLL);
+    }
+}
+
+/// 点击之后等待画面开始变化，超时则认为画面不会再变化
+fn wait_screen_change(adb: &Adb, stability: &FrameStability, before: &RgbaImage) {
+    const TIMEOUT: Duration = Duration::from_secs(2);
+    const POLL: Duration = Duration::from_millis(50);
+    let start = Instant::now();
+    while start.elapsed() < TIMEOUT {
+        if stability.is_changed(before, &adb.screencap()) {
+            log::debug!("screen changed after {:?}", start.elapsed());
+            return;
+        }
+        std::thread::sleep(POLL);
+    }
+    log::debug!("screen unchanged after {:?}", TIMEOUT);
+}
+
+fn identify_screen(
+    pipeline: &Pipeline,
+    screen: &RgbaImage,
+    save_error: &Option<PathBuf>,
+) -> Option<PageQuestion> {
+    let detection = pipeline.detect(screen);
+    log::debug!("detect: {}", detection.timings);
+    let mut edges = detection.edges;
+    match detection.page {
+        Ok(question) => Some(question),
         Err(rects) => {
             if let Some(save_path) = save_error {
                 if !save_path.exists() {
@@ -149,17 +253,15 @@ fn identify_screen(adb: &Adb, save_error: &Option<PathBuf>) -> Option<(RgbaImage
     }
 }
 
-fn edge_detection(img: &RgbaImage) -> GrayImage {
-    let gray: GrayImage = img.convert();
-    imageproc::edges::canny(&gray, 50.0, 150.0)
-}
-
 fn global_init() -> Context {
     let start_time = std::time::Instant::now();
     dotenv();
     let mut ctx = Context::parse();
     ctx.check();
     logging::init(&ctx, start_time);
+    if !ctx.mock_llm.is_empty() {
+        ctx.api_url = MockLlm::start(ctx.mock_llm.clone()).url();
+    }
     ctx
 }
 
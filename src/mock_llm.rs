@@ -0,0 +1,301 @@
+//! 模拟 OpenAI 兼容接口的本地服务，不消耗 token 也能运行完整的答题流程
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use serde_json::json;
+
+use crate::answerer::Answer;
+
+/// 一次请求的响应方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MockKind {
+    /// 总是回答这个选项
+    Fixed(Answer),
+    /// 随机选择一个选项
+    Random,
+    /// 无法解析出答案的文字
+    Garbage,
+    /// 429，被限流
+    RateLimited,
+    /// 500，服务出错
+    ServerError,
+}
+
+/// 一次请求的响应方式以及响应前的等待时间
+///
+/// 格式为 `<A-D|random|garbage|429|500>[@<毫秒>]`，例如 `B`、`random@2000`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MockStep {
+    pub kind: MockKind,
+    pub delay: Duration,
+}
+
+impl FromStr for MockStep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, delay) = match s.trim().split_once('@') {
+            Some((kind, delay)) => {
+                let ms = delay
+                    .parse()
+                    .map_err(|e| format!("invalid delay {delay:?}: {e}"))?;
+                (kind, Duration::from_millis(ms))
+            }
+            None => (s.trim(), Duration::ZERO),
+        };
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "a" => MockKind::Fixed(Answer::A),
+            "b" => MockKind::Fixed(Answer::B),
+            "c" => MockKind::Fixed(Answer::C),
+            "d" => MockKind::Fixed(Answer::D),
+            "random" => MockKind::Random,
+            "garbage" => MockKind::Garbage,
+            "429" => MockKind::RateLimited,
+            "500" => MockKind::ServerError,
+            _ => return Err(format!("unknown mock response: {kind:?}")),
+        };
+        Ok(Self { kind, delay })
+    }
+}
+
+/// 按顺序循环使用 `steps` 响应 `/v1/chat/completions`，直到进程退出
+pub struct MockLlm {
+    addr: String,
+    #[cfg(test)]
+    requests: Arc<AtomicUsize>,
+}
+
+impl MockLlm {
+    pub fn start(steps: Vec<MockStep>) -> Self {
+        assert!(!steps.is_empty(), "no mock responses");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(AtomicUsize::new(0));
+        {
+            let requests = requests.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let index = requests.fetch_add(1, Ordering::SeqCst);
+                    let step = steps[index % steps.len()];
+                    // 每个连接一个线程，集成多个模型时可以同时等待
+                    std::thread::spawn(move || handle(stream, step));
+                }
+            });
+        }
+        log::info!("mock llm: listening on {addr}");
+        Self {
+            addr,
+            #[cfg(test)]
+            requests,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}/v1/chat/completions", self.addr)
+    }
+
+    /// 收到的请求数
+    #[cfg(test)]
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+fn handle(stream: TcpStream, step: MockStep) {
+    let mut reader = BufReader::new(stream);
+    let (status, content_type, body) = match read_request(&mut reader) {
+        Ok(request) => {
+            std::thread::sleep(step.delay);
+            let response = respond(&request, step.kind);
+            log::debug!(
+                "mock llm: {} {}: {step:?}, status: {}",
+                request.method,
+                request.path,
+                response.0
+            );
+            response
+        }
+        Err(e) => {
+            log::warn!("mock llm: malformed request: {e}");
+            error(400, &e)
+        }
+    };
+    let mut stream = reader.into_inner();
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {status} MOCK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+}
+
+/// OpenAI 格式的错误响应
+fn error(status: u16, message: &str) -> (u16, &'static str, String) {
+    let body = json!({ "error": { "message": message } });
+    (status, "application/json", body.to_string())
+}
+
+/// 返回状态码、`Content-Type` 以及响应体
+fn respond(request: &Request, kind: MockKind) -> (u16, &'static str, String) {
+    if request.method != "POST" || !request.path.ends_with("/chat/completions") {
+        return error(404, "not found");
+    }
+    if request.header("Authorization").is_none() {
+        return error(401, "missing api key");
+    }
+    let body: serde_json::Value = serde_json::from_str(&request.body).unwrap_or_default();
+    let answer = match kind {
+        MockKind::Fixed(answer) => Some(answer),
+        MockKind::Random => Some(Answer::random()),
+        MockKind::Garbage => None,
+        MockKind::RateLimited => return error(429, "rate limit exceeded, please retry later"),
+        MockKind::ServerError => return error(500, "internal server error"),
+    };
+    let content = match answer {
+        Some(answer) if body.get("response_format").is_some() => {
+            json!({ "answer": answer.to_str(), "confidence": 0.9 }).to_string()
+        }
+        Some(answer) => answer.to_str().to_owned(),
+        None => "这张图片看不清楚，我无法判断。".to_owned(),
+    };
+    // 粗略估计，每 4 个字节一个 token
+    let usage = json!({
+        "prompt_tokens": request.body.len() / 4,
+        "completion_tokens": content.chars().count(),
+    });
+    if body["stream"] == true {
+        let chunks = [
+            json!({ "choices": [{ "index": 0, "delta": { "role": "assistant", "content": content } }] }),
+            json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }] }),
+            json!({ "choices": [], "usage": usage }),
+        ];
+        let sse: String = chunks.iter().map(|x| format!("data: {x}\n\n")).collect();
+        return (200, "text/event-stream", format!("{sse}data: [DONE]\n\n"));
+    }
+    let body = json!({
+        "object": "chat.completion",
+        "model": body["model"],
+        "choices": [{
+            "index": 0,
+            "finish_reason": "stop",
+            "message": { "role": "assistant", "content": content },
+        }],
+        "usage": usage,
+    });
+    (200, "application/json", body.to_string())
+}
+
+/// 收到的 HTTP 请求
+#[derive(Clone, Debug)]
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: String,
+}
+
+impl Request {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// 读取一个请求，请求不完整或者格式错误时返回原因
+pub(crate) fn read_request(reader: &mut impl BufRead) -> Result<Request, String> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| e.to_string())?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(format!("invalid request line: {line:?}"));
+    };
+    let (method, path) = (method.to_owned(), path.to_owned());
+
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Err("unexpected end of headers".to_owned());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((key.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+
+    let len = match headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+    {
+        Some((_, value)) => value
+            .parse::<usize>()
+            .map_err(|e| format!("invalid content-length {value:?}: {e}"))?,
+        None => 0,
+    };
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    Ok(Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8(body).map_err(|e| e.to_string())?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_step() {
+        let step = |s: &str| s.parse::<MockStep>().unwrap();
+        assert_eq!(step("b").kind, MockKind::Fixed(Answer::B));
+        assert_eq!(
+            step("random@1500"),
+            MockStep {
+                kind: MockKind::Random,
+                delay: Duration::from_millis(1500),
+            }
+        );
+        assert_eq!(step(" 429 ").kind, MockKind::RateLimited);
+        assert!("E".parse::<MockStep>().is_err());
+        assert!("500@soon".parse::<MockStep>().is_err());
+    }
+
+    #[test]
+    fn test_malformed_request() {
+        let server = MockLlm::start(vec!["B".parse().unwrap()]);
+        let addr = server.url();
+        let addr = addr
+            .trim_start_matches("http://")
+            .split_once('/')
+            .unwrap()
+            .0;
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST /v1/chat/completions HTTP/1.1\r\nContent-Length: many\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 "), "{response}");
+        assert!(response.contains("invalid content-length"), "{response}");
+    }
+}
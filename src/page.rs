@@ -1,11 +1,10 @@
-use std::{fmt::Display, ops::Sub};
+use std::{
+    fmt::Display,
+    ops::{Range, Sub},
+};
 
 use image::GrayImage;
-use imageproc::{
-    contours::{Contour, find_contours},
-    point::Point,
-    rect::Rect,
-};
+use imageproc::{contours::Contour, point::Point, rect::Rect};
 use num_traits::Bounded;
 
 use crate::answerer::Answer;
@@ -20,30 +19,17 @@ impl PageQuestion {
         &self.check_boxes[ans as usize]
     }
 
-    pub(crate) fn match_page(edges: &GrayImage) -> Result<Self, Vec<Rect>> {
-        let width = edges.width();
-        let height = edges.height();
-
-        let contours_vec = find_contours::<i32>(edges);
+    /// `gray` 是缩小了 `scale` 倍的截图，`contours` 来自它的边缘，
+    /// 匹配成功时返回原始分辨率下的坐标，失败时返回缩小后的坐标
+    pub(crate) fn match_contours(
+        contours_vec: &[Contour<i32>],
+        gray: &GrayImage,
+        scale: u32,
+    ) -> Result<Self, Vec<Rect>> {
+        let width = gray.width();
         log::debug!("contours: {}", contours_vec.len());
 
-        let contours = contours_vec.iter().filter(|x| {
-            if x.points.len() < width as usize {
-                return false;
-            }
-            let rect = bounding_rect(x);
-            if rect.width() < width / 2 {
-                return false;
-            }
-            true
-        });
-
-        let rects: Vec<_> = contours.map(bounding_rect).collect();
-        log::debug!("rects: {}", rects.len());
-        log::trace!("rects: {:?}", rects);
-        let choices = nms(&rects);
-        log::debug!("nms: {}", choices.len());
-        log::trace!("choices: {:?}", choices);
+        let choices = option_rects(contours_vec, width);
         let choices = choices.into_iter().filter(|x| {
             // 排除宽高比过大或者过小的框
             let ratio = x.width() as f32 / x.height() as f32;
@@ -80,22 +66,44 @@ impl PageQuestion {
             }
         }
 
-        let core = location_core(
-            &contours_vec,
-            (choices[0].top() as u32 + choices[0].height()) as usize,
-            choices[0].height() as usize,
-            width as usize,
-            height as usize,
-        );
+        let core = match locate_question(contours_vec, gray, &choices, scale as usize) {
+            Ok(core) => core,
+            Err(err) => {
+                log::warn!("failed to locate question: {err}");
+                return Err(choices);
+            }
+        };
         log::trace!("core: {:?}", core);
 
         Ok(PageQuestion {
             core,
-            check_boxes: choices,
+            check_boxes: choices.iter().map(|x| x.scale(scale)).collect(),
         })
     }
 }
 
+/// 找出足够大的框作为候选的选项
+fn option_rects(contours: &[Contour<i32>], width: u32) -> Vec<Rect> {
+    let contours = contours.iter().filter(|x| {
+        if x.points.len() < width as usize {
+            return false;
+        }
+        let rect = bounding_rect(x);
+        if rect.width() < width / 2 {
+            return false;
+        }
+        true
+    });
+
+    let rects: Vec<_> = contours.map(bounding_rect).collect();
+    log::debug!("rects: {}", rects.len());
+    log::trace!("rects: {:?}", rects);
+    let rects = nms(&rects);
+    log::debug!("nms: {}", rects.len());
+    log::trace!("nms: {:?}", rects);
+    rects
+}
+
 fn is_difference_small<T>(data: impl Iterator<Item = T>, threshold: T) -> bool
 where
     T: Bounded + PartialOrd + Copy + Sub<Output = T> + Display,
@@ -119,58 +127,112 @@ where
     small
 }
 
-fn location_core(
+/// 定位题目区域失败的原因
+#[derive(Debug)]
+pub(crate) enum LocateError {
+    /// 选项上方没有找到题干的文字
+    NoStem,
+    /// 题目区域的高度太小
+    TooSmall(usize),
+}
+
+impl Display for LocateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocateError::NoStem => write!(f, "no question text above the options"),
+            LocateError::TooSmall(h) => write!(f, "question region too small: {h}"),
+        }
+    }
+}
+
+/// 根据页面结构定位题目区域（题干和选项）：按行统计轮廓点找出选项上方的文字块，
+/// 相邻的文字块间距足够小的属于同一个题干；题干所在卡片的背景色和页面不同时，
+/// 以卡片的上边界作为题目区域的上边界
+fn locate_question(
     contours: &[Contour<i32>],
-    center: usize,
-    box_h: usize,
-    img_w: usize,
-    img_h: usize,
-) -> Rect {
-    let mut point_count = vec![0; img_h];
+    gray: &GrayImage,
+    choices: &[Rect],
+    scale: usize,
+) -> Result<Rect, LocateError> {
+    let (img_w, img_h) = (gray.width() as usize, gray.height() as usize);
+    let box_h = choices[0].height() as usize;
+    let options_top = choices[0].top().max(0) as usize;
+    let options_bottom = choices[choices.len() - 1].bottom() as usize + 1;
+
+    let mut point_count = vec![0usize; img_h];
     for contour in contours {
         for point in &contour.points {
             point_count[point.y as usize] += 1;
         }
     }
 
-    let mut top_y = 0;
-    let mut begin = center;
-    const THRESHOLD: i32 = 42;
-    for i in (0..center).rev() {
-        if point_count[i] < THRESHOLD {
-            continue;
-        }
-        if begin - i > box_h + 1 {
-            let off = box_h / 4 * 3;
-            top_y = begin.max(off) - off;
+    let blocks = text_blocks(&point_count[..options_top]);
+    log::trace!("text blocks: {:?}", blocks);
+    // 题干的最后一行和第一个选项之间不会超过一个选项的高度
+    let mut stem = match blocks.last() {
+        Some(last) if options_top - last.end <= box_h => last.clone(),
+        _ => return Err(LocateError::NoStem),
+    };
+    let max_gap = box_h / 2;
+    for block in blocks.iter().rev().skip(1) {
+        if stem.start - block.end > max_gap {
             break;
         }
-        begin = i;
+        stem.start = block.start;
     }
-    let mut bottom_y = img_h;
-    let mut begin = center;
-    for (i, item) in point_count.iter().enumerate().take(img_h).skip(center) {
-        if *item < THRESHOLD {
+    log::trace!("stem rows: {:?}", stem);
+
+    let pad = box_h / 4;
+    let top = card_top(gray, stem.start, box_h).unwrap_or(stem.start.saturating_sub(pad));
+    let bottom = (options_bottom + pad).min(img_h);
+    align_rows(top * scale, bottom * scale, img_w * scale)
+}
+
+/// 连续有轮廓点的行组成一个文字块
+fn text_blocks(point_count: &[usize]) -> Vec<Range<usize>> {
+    const MIN_POINTS: usize = 2;
+    let mut blocks: Vec<Range<usize>> = vec![];
+    for (y, count) in point_count.iter().enumerate() {
+        if *count < MIN_POINTS {
             continue;
         }
-        if i - begin > box_h + 1 {
-            bottom_y = (begin + box_h / 2).min(img_h);
-            break;
+        match blocks.last_mut() {
+            Some(last) if last.end == y => last.end = y + 1,
+            _ => blocks.push(y..y + 1),
         }
-        begin = i;
     }
-    const ALIGN: usize = 28;
-    let h = bottom_y - top_y;
-    assert!(h > ALIGN, "height: {}", h);
-    let h = h / ALIGN * ALIGN;
-    let mut top_y = top_y + (ALIGN - 1) / ALIGN * ALIGN;
-    let bottom_y = bottom_y + (ALIGN - 1) / ALIGN * ALIGN;
-
-    if bottom_y - top_y > h {
-        top_y += ALIGN / 2;
+    blocks
+}
+
+/// 题干所在卡片的上边界，只在题干上方 `box_h` 行内查找背景色的变化
+fn card_top(gray: &GrayImage, stem_top: usize, box_h: usize) -> Option<usize> {
+    const THRESHOLD: u8 = 3;
+    let card = row_background(gray, stem_top.checked_sub(1)?);
+    (stem_top.saturating_sub(box_h)..stem_top)
+        .rev()
+        .find(|y| row_background(gray, *y).abs_diff(card) > THRESHOLD)
+        .map(|y| y + 1)
+}
+
+/// 一行中出现最多的颜色
+fn row_background(gray: &GrayImage, y: usize) -> u8 {
+    let mut hist = [0u32; 256];
+    for x in 0..gray.width() {
+        hist[gray.get_pixel(x, y as u32)[0] as usize] += 1;
     }
+    (0..=255u8).max_by_key(|x| hist[*x as usize]).unwrap()
+}
 
-    Rect::at(0, top_y as i32).of_size(img_w as u32, h as u32)
+/// 高度对齐到模型切分图片的块大小，多出来的部分上下平均裁掉
+fn align_rows(top: usize, bottom: usize, img_w: usize) -> Result<Rect, LocateError> {
+    const ALIGN: usize = 28;
+    let h = bottom.saturating_sub(top);
+    if h < ALIGN {
+        return Err(LocateError::TooSmall(h));
+    }
+    let aligned = h / ALIGN * ALIGN;
+    let top = top + (h - aligned) / 2;
+    Ok(Rect::at(0, top as i32).of_size(img_w as u32, aligned as u32))
 }
 
 fn nms(rects: &[Rect]) -> Vec<Rect> {
@@ -219,6 +281,7 @@ pub(crate) trait RectExtra {
     fn area(&self) -> u64;
     fn iou(&self, other: &Self) -> f32;
     fn center(&self) -> Point<i32>;
+    fn scale(&self, factor: u32) -> Self;
     #[allow(dead_code)]
     fn contains(&self, point: Point<i32>) -> bool;
 }
@@ -248,6 +311,11 @@ impl RectExtra for Rect {
         Point::new(x, y)
     }
 
+    fn scale(&self, factor: u32) -> Self {
+        Rect::at(self.left() * factor as i32, self.top() * factor as i32)
+            .of_size(self.width() * factor, self.height() * factor)
+    }
+
     fn contains(&self, point: Point<i32>) -> bool {
         point.x >= self.left()
             && point.x <= self.right()
@@ -255,3 +323,65 @@ impl RectExtra for Rect {
             && point.y <= self.bottom()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fixtures, logging,
+        vision::{DEFAULT_BAND, Pipeline},
+    };
+
+    use super::*;
+
+    fn contains(outer: &Rect, inner: &Rect) -> bool {
+        outer.left() <= inner.left()
+            && outer.top() <= inner.top()
+            && outer.right() >= inner.right()
+            && outer.bottom() >= inner.bottom()
+    }
+
+    #[test]
+    fn test_locate_question() {
+        logging::init_for_test();
+        for lines in 1..=6 {
+            let screen = fixtures::question_screen(lines);
+            for scale in [1, 2] {
+                let page = Pipeline::new(scale, DEFAULT_BAND)
+                    .detect(&screen.image)
+                    .page;
+                let page = page.unwrap_or_else(|_| panic!("lines: {lines}, scale: {scale}"));
+                let core = page.core;
+                let stem = screen.stem.unwrap();
+                assert!(contains(&core, &stem), "{lines}: {core:?} {stem:?}");
+                for option in &screen.options {
+                    assert!(contains(&core, option), "{lines}: {core:?} {option:?}");
+                }
+                assert!(core.top() > screen.header.bottom(), "{lines}: {core:?}");
+                assert!(core.top() >= screen.card.top() - 14, "{lines}: {core:?}");
+                assert_eq!(core.height() % 28, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_locate_question_without_stem() {
+        logging::init_for_test();
+        let screen = fixtures::question_screen(0);
+        assert!(
+            Pipeline::new(2, DEFAULT_BAND)
+                .detect(&screen.image)
+                .page
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_align_rows() {
+        assert_eq!(
+            align_rows(10, 20, 100).unwrap_err().to_string(),
+            "question region too small: 10"
+        );
+        let rect = align_rows(100, 160, 100).unwrap();
+        assert_eq!((rect.top(), rect.height()), (102, 56));
+    }
+}
@@ -0,0 +1,270 @@
+//! 发送给模型的提示词模板
+//!
+//! 模板文件按题目分类选择，`<dir>/<category>.txt` 不存在时使用 `<dir>/default.txt`，
+//! 都不存在时使用内置的模板。文件的格式：
+//!
+//! ```text
+//! version: history-v2
+//! [system]
+//! 你是一个熟悉中国历史的答题助手。
+//! [examples]
+//! 例如：问题“秦始皇统一六国是在哪一年”，答案是“公元前 221 年”。
+//! [user]
+//! 回答图片里的{{category}}选择题，共有 {{option_count}} 个选项。{{examples}}{{format}}
+//! ```
+//!
+//! `version:` 行可以省略，省略时使用文件内容的哈希。没有分段时整个文件作为 `[user]`。
+
+use std::path::Path;
+
+use crate::{
+    compose::{ImageLayout, Question},
+    context::Context,
+};
+
+/// 渲染后的提示词
+#[derive(Clone, Debug, Default)]
+pub struct Prompt {
+    pub system: Option<String>,
+    pub user: String,
+    /// 模板的版本，用于在日志中区分不同模板的回答
+    pub version: String,
+}
+
+/// 模板中可以使用的变量
+const VARIABLES: [&str; 5] = ["layout", "option_count", "category", "examples", "format"];
+
+#[derive(Clone, Debug)]
+pub struct Template {
+    system: Option<String>,
+    user: String,
+    examples: String,
+    version: String,
+    category: String,
+}
+
+impl Template {
+    pub fn from_args(ctx: &Context) -> Self {
+        let category = ctx.question_category.clone().unwrap_or_default();
+        let Some(dir) = &ctx.prompt_dir else {
+            return Self::builtin(category);
+        };
+        let path = [category.as_str(), "default"]
+            .iter()
+            .filter(|x| !x.is_empty())
+            .map(|x| dir.join(format!("{x}.txt")))
+            .find(|x| x.exists());
+        match path {
+            Some(path) => Self::load(&path, category),
+            None => {
+                log::warn!(
+                    "No prompt template for category {category:?} in {}, use the builtin one",
+                    dir.display()
+                );
+                Self::builtin(category)
+            }
+        }
+    }
+
+    pub fn builtin(category: String) -> Self {
+        Self {
+            system: None,
+            user: "{{layout}}你的回答会被代码解析，{{format}}".to_owned(),
+            examples: String::new(),
+            version: "builtin".to_owned(),
+            category,
+        }
+    }
+
+    pub fn load(path: &Path, category: String) -> Self {
+        let content = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()));
+        let template = Self::parse(&content, category)
+            .unwrap_or_else(|e| panic!("invalid prompt template {}: {e}", path.display()));
+        log::info!(
+            "prompt template: {}, version: {}",
+            path.display(),
+            template.version
+        );
+        template
+    }
+
+    fn parse(content: &str, category: String) -> Result<Self, String> {
+        let mut version = None;
+        let mut sections: Vec<(String, String)> = vec![];
+        for (i, line) in content.lines().enumerate() {
+            let trimmed = line.trim();
+            if i == 0
+                && let Some(v) = trimmed.strip_prefix("version:")
+            {
+                version = Some(v.trim().to_owned());
+                continue;
+            }
+            if let Some(name) = trimmed.strip_prefix('[').and_then(|x| x.strip_suffix(']'))
+                && ["system", "examples", "user"].contains(&name)
+            {
+                sections.push((name.to_owned(), String::new()));
+                continue;
+            }
+            if sections.is_empty() {
+                sections.push(("user".to_owned(), String::new()));
+            }
+            let text = &mut sections.last_mut().unwrap().1;
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(line);
+        }
+        let section = |name: &str| {
+            sections
+                .iter()
+                .find(|(x, _)| x == name)
+                .map(|(_, x)| x.trim().to_owned())
+        };
+        let user = section("user").ok_or("missing [user] section")?;
+        let system = section("system").filter(|x| !x.is_empty());
+        for text in [&user, system.as_ref().unwrap_or(&user)] {
+            check_variables(text)?;
+        }
+        Ok(Self {
+            system,
+            user,
+            examples: section("examples").unwrap_or_default(),
+            version: version.unwrap_or_else(|| format!("{:016x}", fnv1a(content.as_bytes()))),
+            category,
+        })
+    }
+
+    pub fn render(&self, question: &Question, structured: bool) -> Prompt {
+        let format = format_instruction(structured, question.transcribe, question.verify);
+        let option_count = question.option_count.to_string();
+        let values = [
+            layout_description(question),
+            &option_count,
+            &self.category,
+            &self.examples,
+            format,
+        ];
+        let fill = |text: &str| {
+            VARIABLES
+                .iter()
+                .zip(values)
+                .fold(text.to_owned(), |text, (name, value)| {
+                    text.replace(&format!("{{{{{name}}}}}"), value)
+                })
+        };
+        Prompt {
+            system: self.system.as_deref().map(fill),
+            user: fill(&self.user),
+            version: self.version.clone(),
+        }
+    }
+}
+
+/// 模板中只能使用 `VARIABLES` 中的变量
+fn check_variables(text: &str) -> Result<(), String> {
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            return Err(format!("unclosed variable: {}", &rest[start..]));
+        };
+        let name = rest[start + 2..start + end].trim();
+        if !VARIABLES.contains(&name) {
+            return Err(format!("unknown variable: {name}"));
+        }
+        rest = &rest[start + end + 2..];
+    }
+    Ok(())
+}
+
+/// 不同版本的 Rust 中结果不变的哈希，用于提示词的版本和记录中的图片
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, x| {
+        (hash ^ *x as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// 说明图片的组织方式
+fn layout_description(question: &Question) -> &'static str {
+    match (question.layout, question.labeled) {
+        _ if question.images.is_empty() => "回答下面的选择题，",
+        (ImageLayout::Core, false) => "回答图片里的选择题，",
+        (ImageLayout::Core, true) => "回答图片里的选择题，每个选项右侧标注了选项字母，",
+        (ImageLayout::Stitch, _) => {
+            "回答图片里的选择题，图片上方是题干，下方是选项，每个选项左侧标注了选项字母，"
+        }
+        (ImageLayout::Parts, _) => {
+            "回答选择题，第一张图片是题干，后面的图片依次是选项 A、B、C、D，"
+        }
+    }
+}
+
+/// 自我检查时第二轮对话的提示词
+pub const VERIFY_PROMPT: &str = "对照图片检查你的答案是否正确，如果有错误请改正。直接输出最终的选项字母，仅输出选项字母，不需要多余的解释。";
+
+/// 要求的输出格式，自我检查的第一轮要求先分析再回答
+fn format_instruction(structured: bool, transcribe: bool, verify: bool) -> &'static str {
+    match (structured, transcribe) {
+        _ if verify => {
+            "先简要分析题目和每个选项，最后单独一行输出“答案：”和你认为最合适的选项字母，即使不确定也必须选择一个选项。"
+        }
+        (true, true) => {
+            "输出一个 JSON 对象，question 是题干的原文，options 是按顺序排列的四个选项的原文，不包括选项字母，answer 是你认为最合适的选项字母，confidence 是 0 到 1 之间的数字，表示你对答案的把握，不需要多余的解释，即使不确定也必须选择一个选项。"
+        }
+        (true, false) => {
+            "输出一个 JSON 对象，answer 是你认为最合适的选项字母，confidence 是 0 到 1 之间的数字，表示你对答案的把握，不需要多余的解释，即使不确定也必须选择一个选项。"
+        }
+        (false, _) => {
+            "直接输出你认为最合适的选项字母，仅输出选项字母，不需要多余的解释，即使不确定也必须选择一个选项。"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin() {
+        let prompt = Template::builtin(String::new()).render(&Question::for_test(), false);
+        assert_eq!(
+            prompt.user,
+            "回答图片里的选择题，你的回答会被代码解析，直接输出你认为最合适的选项字母，仅输出选项字母，不需要多余的解释，即使不确定也必须选择一个选项。"
+        );
+        assert!(prompt.system.is_none());
+        assert_eq!(prompt.version, "builtin");
+    }
+
+    #[test]
+    fn test_parse() {
+        let content = "version: history-v2\n\
+            [system]\n\
+            你是一个熟悉{{category}}的答题助手。\n\
+            [examples]\n\
+            例如：秦始皇统一六国是在公元前 221 年。\n\
+            [user]\n\
+            共有 {{option_count}} 个选项。{{examples}}\n\
+            {{format}}";
+        let template = Template::parse(content, "历史".to_owned()).unwrap();
+        let prompt = template.render(&Question::for_test(), true);
+        assert_eq!(prompt.version, "history-v2");
+        assert_eq!(prompt.system.unwrap(), "你是一个熟悉历史的答题助手。");
+        assert!(prompt.user.starts_with(
+            "共有 4 个选项。例如：秦始皇统一六国是在公元前 221 年。\n输出一个 JSON 对象"
+        ));
+
+        // 没有分段和版本时整个文件是 [user]，版本是内容的哈希
+        let template = Template::parse("{{layout}}{{format}}", String::new()).unwrap();
+        assert!(template.system.is_none());
+        assert_eq!(template.version.len(), 16);
+        assert_ne!(
+            template.version,
+            Template::parse("{{format}}", String::new())
+                .unwrap()
+                .version
+        );
+
+        assert!(Template::parse("{{answer}}", String::new()).is_err());
+        assert!(Template::parse("[system]\n{{format}}", String::new()).is_err());
+    }
+}
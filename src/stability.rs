@@ -0,0 +1,110 @@
+use std::time::{Duration, Instant};
+
+use image::{GrayImage, RgbaImage, buffer::ConvertBuffer, imageops};
+
+use crate::context::Context;
+
+/// 通过比较连续的截图判断画面是否静止，用于等待过渡动画结束
+pub(crate) struct FrameStability {
+    stable_time: Duration,
+    threshold: f32,
+    last: Option<GrayImage>,
+    stable_since: Instant,
+}
+
+impl FrameStability {
+    pub(crate) fn from_args(ctx: &Context) -> Self {
+        Self::new(
+            Duration::from_millis(ctx.screen_stable_ms),
+            ctx.screen_diff_threshold,
+        )
+    }
+
+    pub(crate) fn new(stable_time: Duration, threshold: f32) -> Self {
+        Self {
+            stable_time,
+            threshold,
+            last: None,
+            stable_since: Instant::now(),
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.last = None;
+        self.stable_since = Instant::now();
+    }
+
+    /// 输入新的一帧，返回画面是否已经静止了足够长的时间
+    pub(crate) fn update(&mut self, frame: &RgbaImage) -> bool {
+        self.update_at(downsample(frame), Instant::now())
+    }
+
+    /// 判断两帧之间是否有变化
+    pub(crate) fn is_changed(&self, a: &RgbaImage, b: &RgbaImage) -> bool {
+        frame_difference(&downsample(a), &downsample(b)) >= self.threshold
+    }
+
+    fn update_at(&mut self, frame: GrayImage, now: Instant) -> bool {
+        let Some(last) = self.last.replace(frame) else {
+            self.stable_since = now;
+            return false;
+        };
+        let diff = frame_difference(&last, self.last.as_ref().unwrap());
+        log::trace!("frame difference: {diff:.3}");
+        if diff >= self.threshold {
+            self.stable_since = now;
+            return false;
+        }
+        now.duration_since(self.stable_since) >= self.stable_time
+    }
+}
+
+fn downsample(frame: &RgbaImage) -> GrayImage {
+    const WIDTH: u32 = 64;
+    let gray: GrayImage = frame.convert();
+    let height = (gray.height() * WIDTH / gray.width().max(1)).max(1);
+    imageops::thumbnail(&gray, WIDTH, height)
+}
+
+/// 两帧之间平均每个像素的差值，尺寸不同视为完全不同
+fn frame_difference(a: &GrayImage, b: &GrayImage) -> f32 {
+    if a.dimensions() != b.dimensions() {
+        return f32::MAX;
+    }
+    let sum: u64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(x, y)| x.abs_diff(*y) as u64)
+        .sum();
+    sum as f32 / a.as_raw().len().max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Luma;
+
+    use super::*;
+
+    #[test]
+    fn test_stable_after_duration() {
+        let mut stability = FrameStability::new(Duration::from_millis(300), 2.0);
+        let start = Instant::now();
+        let frame = GrayImage::from_pixel(64, 128, Luma([200]));
+        let moved = GrayImage::from_pixel(64, 128, Luma([100]));
+
+        assert!(!stability.update_at(frame.clone(), start));
+        assert!(!stability.update_at(frame.clone(), start + Duration::from_millis(100)));
+        assert!(!stability.update_at(moved.clone(), start + Duration::from_millis(200)));
+        assert!(!stability.update_at(moved.clone(), start + Duration::from_millis(400)));
+        assert!(stability.update_at(moved, start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_frame_difference() {
+        let a = GrayImage::from_pixel(4, 4, Luma([10]));
+        let b = GrayImage::from_pixel(4, 4, Luma([14]));
+        assert_eq!(frame_difference(&a, &b), 4.0);
+        assert_eq!(frame_difference(&a, &GrayImage::new(2, 2)), f32::MAX);
+    }
+}
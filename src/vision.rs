@@ -0,0 +1,259 @@
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use image::{GrayImage, RgbaImage, buffer::ConvertBuffer, imageops};
+use imageproc::{contours::find_contours, rect::Rect};
+
+use crate::{context::Context, page::PageQuestion};
+
+/// 默认的纵向范围，去掉顶部的状态栏和标题以及底部的导航栏
+pub(crate) const DEFAULT_BAND: (f32, f32) = (0.1, 0.95);
+
+/// 识别答题页面：先在缩小的截图上找到选项，再只在选项所在的区域内用原始分辨率修正选项的位置
+pub(crate) struct Pipeline {
+    scale: u32,
+    /// 题目和选项所在的纵向范围，按截图高度的比例，只在这个范围内检测边缘和轮廓
+    band: (f32, f32),
+}
+
+pub(crate) struct Detection {
+    pub(crate) page: Result<PageQuestion, Vec<Rect>>,
+    /// 缩小后的截图的边缘，匹配失败时的坐标基于这张图
+    pub(crate) edges: GrayImage,
+    pub(crate) timings: Timings,
+}
+
+impl Pipeline {
+    pub(crate) fn from_args(ctx: &Context) -> Self {
+        Self::new(ctx.detect_scale, (ctx.detect_band[0], ctx.detect_band[1]))
+    }
+
+    pub(crate) fn new(scale: u32, band: (f32, f32)) -> Self {
+        assert!(scale > 0);
+        assert!(0.0 <= band.0 && band.0 < band.1 && band.1 <= 1.0);
+        Self { scale, band }
+    }
+
+    /// `height` 中属于 `band` 的行
+    fn band_rows(&self, height: u32) -> (u32, u32) {
+        let top = (height as f32 * self.band.0).floor() as u32;
+        let bottom = (height as f32 * self.band.1).ceil() as u32;
+        (top.min(height), bottom.min(height))
+    }
+
+    pub(crate) fn detect(&self, screen: &RgbaImage) -> Detection {
+        let mut timings = Timings::default();
+        let gray: GrayImage = timings.stage("gray", || screen.convert());
+        let small = timings.stage("downscale", || downscale(&gray, self.scale));
+        let small = small.as_ref().unwrap_or(&gray);
+        let (top, bottom) = self.band_rows(small.height());
+        let roi = timings.stage("roi", || {
+            imageops::crop_imm(small, 0, top, small.width(), bottom - top).to_image()
+        });
+        let roi_edges = timings.stage("canny", || edge_detection(&roi, self.scale));
+        let contours = timings.stage("contours", || {
+            let mut contours = find_contours::<i32>(&roi_edges);
+            // 换算回整张截图的坐标
+            for point in contours.iter_mut().flat_map(|x| x.points.iter_mut()) {
+                point.y += top as i32;
+            }
+            contours
+        });
+        let page = timings.stage("match", || {
+            PageQuestion::match_contours(&contours, small, self.scale)
+        });
+        // 范围之外没有检测边缘，保存的边缘图中留空
+        let mut edges = GrayImage::new(small.width(), small.height());
+        imageops::replace(&mut edges, &roi_edges, 0, top as i64);
+        let page = page.map(|mut page| {
+            if self.scale != 1 {
+                timings.stage("refine", || {
+                    page.check_boxes = refine_choices(&gray, &page.check_boxes, self.scale);
+                });
+            }
+            page
+        });
+        Detection {
+            page,
+            edges,
+            timings,
+        }
+    }
+}
+
+/// 缩小后细线的梯度会变弱，阈值跟着缩小的倍数降低
+fn edge_detection(gray: &GrayImage, scale: u32) -> GrayImage {
+    let scale = scale as f32;
+    imageproc::edges::canny(gray, 50.0 / scale, 150.0 / scale)
+}
+
+/// 缩小截图，每个块取与块内均值相差最大的像素，直接平均会把选项的细边框淡化到检测不出边缘
+fn downscale(gray: &GrayImage, scale: u32) -> Option<GrayImage> {
+    if scale == 1 {
+        return None;
+    }
+    let width = gray.width() / scale;
+    let height = gray.height() / scale;
+    Some(GrayImage::from_fn(width, height, |x, y| {
+        let (mut min, mut max, mut sum) = (u8::MAX, u8::MIN, 0u32);
+        for dy in 0..scale {
+            for dx in 0..scale {
+                let val = gray.get_pixel(x * scale + dx, y * scale + dy)[0];
+                min = min.min(val);
+                max = max.max(val);
+                sum += val as u32;
+            }
+        }
+        let mean = sum / (scale * scale);
+        if mean - min as u32 > max as u32 - mean {
+            image::Luma([min])
+        } else {
+            image::Luma([max])
+        }
+    }))
+}
+
+/// 在原始分辨率下修正选项的位置，`coarse` 是由缩小的截图放大回来的位置，
+/// 只在每条边附近 `scale` 个像素的范围内查找梯度最大的行或列
+fn refine_choices(gray: &GrayImage, coarse: &[Rect], scale: u32) -> Vec<Rect> {
+    let margin = scale as i32 * 2;
+    coarse
+        .iter()
+        .map(|x| {
+            let (l, t, r, b) = (x.left(), x.top(), x.right(), x.bottom());
+            let (w4, h4) = (x.width() as i32 / 4, x.height() as i32 / 4);
+            // 上下边沿着中间的列比较，左右边沿着中间的行比较
+            let cols = (l + w4)..(r - w4);
+            let rows = (t + h4)..(b - h4);
+            let top = strongest_edge(gray, (t - margin)..(t + margin), cols.clone(), -1, false);
+            let bottom = strongest_edge(gray, (b - margin)..(b + margin), cols, 1, false);
+            let left = strongest_edge(gray, (l - margin)..(l + margin), rows.clone(), -1, true);
+            let right = strongest_edge(gray, (r - margin)..(r + margin), rows, 1, true);
+            match (top, bottom, left, right) {
+                (Some(t), Some(b), Some(l), Some(r)) if t < b && l < r => {
+                    Rect::at(l, t).of_size((r - l + 1) as u32, (b - t + 1) as u32)
+                }
+                _ => {
+                    log::debug!("refine failed: {x:?}");
+                    *x
+                }
+            }
+        })
+        .collect()
+}
+
+/// 在 `range` 中找到与相邻的外侧（`dir` 方向）行或列差异最大的位置，
+/// `vertical` 为 true 时 `range` 是列的范围，`along` 是行的范围
+fn strongest_edge(
+    gray: &GrayImage,
+    range: std::ops::Range<i32>,
+    along: std::ops::Range<i32>,
+    dir: i32,
+    vertical: bool,
+) -> Option<i32> {
+    let (w, h) = (gray.width() as i32, gray.height() as i32);
+    let (limit, along_limit) = if vertical { (w, h) } else { (h, w) };
+    let along = along.start.max(0)..along.end.min(along_limit);
+    if along.is_empty() {
+        return None;
+    }
+    let pixel = |pos: i32, i: i32| {
+        let (x, y) = if vertical { (pos, i) } else { (i, pos) };
+        gray.get_pixel(x as u32, y as u32)[0] as i32
+    };
+    range
+        .filter(|pos| *pos >= 0 && *pos < limit && (0..limit).contains(&(pos + dir)))
+        .map(|pos| {
+            let diff: i32 = along
+                .clone()
+                .map(|i| (pixel(pos, i) - pixel(pos + dir, i)).abs())
+                .sum();
+            (pos, diff)
+        })
+        .filter(|x| x.1 > 0)
+        .max_by_key(|x| x.1)
+        .map(|x| x.0)
+}
+
+/// 各个阶段的耗时
+#[derive(Default)]
+pub(crate) struct Timings {
+    /// 按执行顺序排列的阶段的名字和耗时
+    pub(crate) stages: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    fn stage<T>(&mut self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let res = f();
+        self.stages.push((name, start.elapsed()));
+        res
+    }
+
+    pub(crate) fn total(&self) -> Duration {
+        self.stages.iter().map(|x| x.1).sum()
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, elapsed) in &self.stages {
+            write!(f, "{name}: {:.3}ms, ", elapsed.as_secs_f64() * 1000.0)?;
+        }
+        write!(f, "total: {:.3}ms", self.total().as_secs_f64() * 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fixtures, logging, page::RectExtra};
+
+    use super::*;
+
+    #[test]
+    fn test_detect_downscaled() {
+        logging::init_for_test();
+        let screen = fixtures::question_screen(3);
+        let full = Pipeline::new(1, DEFAULT_BAND)
+            .detect(&screen.image)
+            .page
+            .unwrap();
+        let fast = Pipeline::new(2, DEFAULT_BAND)
+            .detect(&screen.image)
+            .page
+            .unwrap();
+        for ((full, fast), expected) in full
+            .check_boxes
+            .iter()
+            .zip(&fast.check_boxes)
+            .zip(&screen.options)
+        {
+            assert!(full.iou(expected) > 0.95, "{full:?} {expected:?}");
+            assert!(fast.iou(full) > 0.98, "{fast:?} {full:?}");
+        }
+    }
+
+    #[test]
+    fn test_detect_band() {
+        logging::init_for_test();
+        let screen = fixtures::question_screen(3);
+        let detection = Pipeline::new(2, DEFAULT_BAND).detect(&screen.image);
+        assert!(detection.page.is_ok());
+        // 范围之外没有边缘
+        let top = (detection.edges.height() as f32 * DEFAULT_BAND.0) as u32;
+        let above =
+            imageops::crop_imm(&detection.edges, 0, 0, detection.edges.width(), top).to_image();
+        assert!(above.pixels().all(|x| x[0] == 0));
+
+        // 选项在范围之外时识别失败
+        let bottom = screen.options[3].bottom() as f32 / screen.image.height() as f32;
+        assert!(
+            Pipeline::new(2, (0.0, bottom - 0.05))
+                .detect(&screen.image)
+                .page
+                .is_err()
+        );
+    }
+}